@@ -0,0 +1,10 @@
+-- Decoration state of a shareable tree
+CREATE TABLE IF NOT EXISTS trees (
+    id uuid PRIMARY KEY,
+    star_lit boolean NOT NULL DEFAULT false,
+    presents text[] NOT NULL DEFAULT ARRAY['red', 'red', 'red'],
+    ornaments boolean[] NOT NULL DEFAULT ARRAY[false, false, false, false, false, false, false],
+    checksums text[] NOT NULL DEFAULT ARRAY[]::text[],
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
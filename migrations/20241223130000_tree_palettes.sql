@@ -0,0 +1,6 @@
+-- Per tree present cycle rules, NULL falls back to the configured rules
+ALTER TABLE trees
+    ADD COLUMN IF NOT EXISTS palette text,
+    ADD COLUMN IF NOT EXISTS cycle_order text[],
+    ADD COLUMN IF NOT EXISTS random_cycle boolean,
+    ADD COLUMN IF NOT EXISTS skip_colors text[];
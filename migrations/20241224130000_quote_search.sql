@@ -0,0 +1,7 @@
+-- Full-text search over quotes, the author weighs more than the quote itself
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS search tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', author), 'A') ||
+    setweight(to_tsvector('english', quote), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS quotes_search ON quotes USING GIN (search);
@@ -0,0 +1,17 @@
+-- Every revision of a quote, version 1 is the draft
+CREATE TABLE IF NOT EXISTS quote_versions (
+    quote_id uuid NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    version int NOT NULL,
+    author text NOT NULL,
+    quote text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (quote_id, version)
+);
+
+-- Only the current revision of existing quotes is known
+INSERT INTO quote_versions (quote_id, version, author, quote)
+SELECT id, version, author, quote FROM quotes
+ON CONFLICT DO NOTHING;
+
+-- Removed quotes are kept until they're restored or the quotes are reset
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS deleted_at timestamptz;
@@ -0,0 +1,8 @@
+-- Gift keys rotated to with POST /16/keys/rotate, every instance loads them
+CREATE TABLE IF NOT EXISTS gift_keys (
+    kid text PRIMARY KEY,
+    secret text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- set once a newer key is rotated to, the key verifies until then
+    expires_at timestamptz
+);
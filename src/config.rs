@@ -0,0 +1,35 @@
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+// Directory holding the TOML configuration files, overridable for other deployments
+const CONFIG_DIR_VAR: &str = "CONFIG_DIR";
+const DEFAULT_CONFIG_DIR: &str = "config";
+
+pub fn path(name: &str) -> PathBuf {
+    let dir = std::env::var(CONFIG_DIR_VAR).unwrap_or_else(|_| DEFAULT_CONFIG_DIR.to_string());
+    PathBuf::from(dir).join(format!("{name}.toml"))
+}
+
+/// Loads `config/{name}.toml`, falling back to the default configuration when the file is missing.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> T {
+    let path = path(name);
+
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return T::default();
+    };
+
+    toml::from_str(&contents)
+        .unwrap_or_else(|e| panic!("Invalid configuration in {}: {e}", path.display()))
+}
+
+/// A secret given directly in the configuration or read from the named environment variable,
+/// `None` when neither is set. An unset variable is a configuration error.
+pub fn secret(value: Option<&str>, env: Option<&str>) -> Option<String> {
+    match (value, env) {
+        (Some(value), _) => Some(value.to_string()),
+        (None, Some(var)) => Some(
+            std::env::var(var).unwrap_or_else(|_| panic!("Environment variable {var} is not set")),
+        ),
+        (None, None) => None,
+    }
+}
@@ -1,70 +1,214 @@
-use actix_web::{http::header, post, web::ServiceConfig, HttpRequest, HttpResponse};
-use cargo_manifest::Manifest;
+use crate::config;
+use crate::models::dependencies;
+use crate::models::manifest::{resolve_packages, ManifestPolicy, Metadata};
+use actix_multipart::Multipart;
+use actix_web::{
+    guard::GuardContext,
+    http::header::{self, Header},
+    post,
+    web::{self, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use cargo_manifest::{Manifest, Package};
+use futures_util::StreamExt as _;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+
+static POLICY: Lazy<ManifestPolicy> = Lazy::new(|| config::load("manifest_policy"));
+
+// Names of the policy rules a rejected manifest broke
+const POLICY_HEADER: &str = "X-Policy-Violations";
 
 pub fn configure(cfg: &mut ServiceConfig) {
-    cfg.service(manifest);
+    // the multipart route is guarded, so it has to be registered first
+    cfg.service(workspace).service(manifest).service(convert);
 }
 
-#[derive(Default, serde::Deserialize)]
-struct Metadata {
-    #[serde(default)]
-    orders: Vec<Order>,
+fn is_multipart(ctx: &GuardContext) -> bool {
+    ctx.head()
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("multipart/form-data"))
+}
+
+enum ParseError {
+    UnsupportedMediaType,
+    Invalid,
+}
+
+impl From<ParseError> for HttpResponse {
+    fn from(e: ParseError) -> Self {
+        match e {
+            ParseError::UnsupportedMediaType => HttpResponse::UnsupportedMediaType().finish(),
+            ParseError::Invalid => HttpResponse::BadRequest().body("Invalid manifest"),
+        }
+    }
+}
+
+fn parse_manifest<T: DeserializeOwned>(content_type: &str, data: &str) -> Result<T, ParseError> {
+    match content_type {
+        "application/toml" => toml::from_str::<T>(data).map_err(|_| ParseError::Invalid),
+        "application/json" => serde_json::from_str::<T>(data).map_err(|_| ParseError::Invalid),
+        "application/yaml" => serde_yml::from_str::<T>(data).map_err(|_| ParseError::Invalid),
+        _ => Err(ParseError::UnsupportedMediaType),
+    }
+}
+
+fn serialize_manifest<T: Serialize>(content_type: &str, value: &T) -> Option<String> {
+    match content_type {
+        "application/toml" => toml::to_string(value).ok(),
+        "application/json" => serde_json::to_string_pretty(value).ok(),
+        "application/yaml" => serde_yml::to_string(value).ok(),
+        _ => None,
+    }
 }
 
-#[serde_with::serde_as]
-#[derive(serde::Deserialize)]
-struct Order {
-    item: String,
-    #[serde_as(deserialize_as = "serde_with::DefaultOnError")]
+// Content type of an uploaded manifest, taken from the part or the file extension
+fn part_content_type(content_type: Option<&str>, filename: Option<&str>) -> &'static str {
+    match content_type {
+        Some("application/json") => return "application/json",
+        Some("application/yaml") => return "application/yaml",
+        Some("application/toml") => return "application/toml",
+        _ => {}
+    }
+
+    match filename
+        .and_then(|f| f.rsplit_once('.'))
+        .map(|(_, ext)| ext)
+    {
+        Some("json") => "application/json",
+        Some("yaml") | Some("yml") => "application/yaml",
+        // Cargo manifests are TOML unless told otherwise
+        _ => "application/toml",
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+struct ManifestOptions {
+    // report on the dependencies instead of processing orders
     #[serde(default)]
-    quantity: Option<usize>,
+    analyze: bool,
 }
 
 #[post("/5/manifest")]
-async fn manifest(req: HttpRequest, data: String) -> HttpResponse {
+async fn manifest(
+    req: HttpRequest,
+    options: web::Query<ManifestOptions>,
+    data: String,
+) -> HttpResponse {
     // Extract and validate content type
-    // Get package from manifest
-    let Ok(Some(package)) = (match req
+    let Some(ct) = req
         .headers()
         .get(header::CONTENT_TYPE)
         .and_then(|ct| ct.to_str().ok())
-    {
-        Some(ct) => match ct {
-            "application/toml" => toml::from_str::<Manifest<Metadata>>(&data)
-                .map_err(|_| "Invalid TOML Manifest".to_string()),
-            "application/json" => serde_json::from_str::<Manifest<Metadata>>(&data)
-                .map_err(|_| "Invalid JSON Manifest".to_string()),
-            "application/yaml" => serde_yml::from_str::<Manifest<Metadata>>(&data)
-                .map_err(|_| "Invalid YAML manifest".to_string()),
-            _ => return HttpResponse::UnsupportedMediaType().finish(),
-        },
-        None => return HttpResponse::UnsupportedMediaType().finish(),
-    })
-    .map(|m| m.package) else {
+    else {
+        return HttpResponse::UnsupportedMediaType().finish();
+    };
+
+    if options.analyze {
+        return match parse_manifest::<Manifest>(ct, &data) {
+            Ok(parsed) => HttpResponse::Ok().json(dependencies::analyze(&parsed)),
+            Err(e) => e.into(),
+        };
+    }
+
+    let parsed = match parse_manifest(ct, &data) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.into(),
+    };
+
+    // Get the package, resolving anything inherited from its own workspace
+    let Ok(packages) = resolve_packages(vec![parsed]) else {
         return HttpResponse::BadRequest().body("Invalid manifest");
     };
 
-    // Check for code in keyword
-    if !package
-        .keywords
-        .and_then(|k| k.as_local())
-        .map(|k| k.contains(&"Christmas 2024".to_string()))
-        .unwrap_or_default()
-    {
-        return HttpResponse::BadRequest().body("Magic keyword not provided");
+    process_packages(packages, false)
+}
+
+#[post("/5/manifest", guard = "is_multipart")]
+async fn workspace(mut payload: Multipart) -> HttpResponse {
+    // Every uploaded file is a manifest of the workspace, their total size is capped
+    let mut manifests = Vec::new();
+    let mut size = 0;
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(_) => return HttpResponse::BadRequest().finish(),
+        };
+
+        let ct = part_content_type(
+            field.content_type().map(|m| m.essence_str()),
+            field.content_disposition().and_then(|cd| cd.get_filename()),
+        );
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(_) => return HttpResponse::BadRequest().finish(),
+            };
+
+            size += chunk.len();
+            if size > POLICY.max_upload_size {
+                return HttpResponse::PayloadTooLarge().finish();
+            }
+
+            data.extend_from_slice(&chunk);
+        }
+
+        let Ok(data) = String::from_utf8(data) else {
+            return HttpResponse::BadRequest().body("Invalid manifest");
+        };
+
+        match parse_manifest(ct, &data) {
+            Ok(parsed) => manifests.push(parsed),
+            Err(e) => return e.into(),
+        }
     }
 
-    // Process orders
-    let Some(orders) = package.metadata.map(|m| {
-        m.orders
-            .into_iter()
-            .filter(|o| o.quantity.is_some())
-            .map(|o| format!("{}: {}", o.item, o.quantity.unwrap()))
-            .collect::<Vec<String>>()
-    }) else {
-        return HttpResponse::NoContent().finish();
+    let packages = match resolve_packages(manifests) {
+        Ok(packages) => packages,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid manifest: {e}")),
     };
 
+    process_packages(packages, true)
+}
+
+fn process_packages(packages: Vec<Package<Metadata>>, prefix_package: bool) -> HttpResponse {
+    // Check every package against the configured policy
+    let mut violations = Vec::new();
+    let mut rules = Vec::new();
+    for package in &packages {
+        for violation in POLICY.check(package) {
+            if !rules.contains(&violation.rule()) {
+                rules.push(violation.rule());
+            }
+
+            if prefix_package {
+                violations.push(format!("{}: {violation}", package.name));
+            } else {
+                violations.push(violation.to_string());
+            }
+        }
+    }
+
+    // The broken rules are named in a header so the body stays as before
+    if !violations.is_empty() {
+        return HttpResponse::BadRequest()
+            .insert_header((POLICY_HEADER, rules.join(", ")))
+            .body(violations.join("\n"));
+    }
+
+    // Process orders, aggregated across all packages
+    let orders = packages
+        .into_iter()
+        .filter_map(|p| p.metadata)
+        .flat_map(|m| m.orders)
+        .filter(|o| o.quantity.is_some())
+        .map(|o| format!("{}: {}", o.item, o.quantity.unwrap()))
+        .collect::<Vec<String>>();
+
     // return no content if no valid order
     if orders.is_empty() {
         return HttpResponse::NoContent().finish();
@@ -73,3 +217,53 @@ async fn manifest(req: HttpRequest, data: String) -> HttpResponse {
     // Final response
     HttpResponse::Ok().body(orders.join("\n"))
 }
+
+// Output format picked from the Accept header, TOML when anything goes
+fn accepted_content_type(req: &HttpRequest) -> Option<&'static str> {
+    let Ok(accept) = header::Accept::parse(req) else {
+        return Some("application/toml");
+    };
+
+    if accept.is_empty() {
+        return Some("application/toml");
+    }
+
+    accept
+        .ranked()
+        .into_iter()
+        .find_map(|mime| match mime.essence_str() {
+            "application/toml" | "*/*" | "application/*" => Some("application/toml"),
+            "application/json" => Some("application/json"),
+            "application/yaml" => Some("application/yaml"),
+            _ => None,
+        })
+}
+
+#[post("/5/convert")]
+async fn convert(req: HttpRequest, data: String) -> HttpResponse {
+    // Content-Type selects the input format
+    let Some(ct) = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+    else {
+        return HttpResponse::UnsupportedMediaType().finish();
+    };
+
+    // Accept selects the output format
+    let Some(accept) = accepted_content_type(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+
+    // Parse without any metadata schema, so nothing is dropped on the way through
+    let parsed = match parse_manifest::<Manifest>(ct, &data) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.into(),
+    };
+
+    let Some(body) = serialize_manifest(accept, &parsed) else {
+        return HttpResponse::UnprocessableEntity().finish();
+    };
+
+    HttpResponse::Ok().content_type(accept).body(body)
+}
@@ -1,133 +1,1003 @@
+use crate::config;
+use crate::models::claims::{Check, ClaimsError, ClaimsPolicy};
+use crate::models::jwe::{is_jwe, Encrypter, EncryptionConfig};
+use crate::models::jwks::{JwksCache, JwksConfig};
+use crate::models::keyring::{now, random_kid, random_secret, KeyConfig, KeyRing, StoredKey};
+use crate::models::signer::{Signer, SignerConfig};
 use actix_web::{
-    cookie::Cookie,
+    cookie::{time::Duration, Cookie, SameSite},
     get,
-    http::header::COOKIE,
+    http::header::AUTHORIZATION,
     post,
     web::{self, ServiceConfig},
     HttpRequest, HttpResponse,
 };
-use jsonwebtoken::{decode_header, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode_header, Algorithm, DecodingKey, Header, Validation};
 use once_cell::sync::Lazy;
-use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::RwLock;
+use uuid::Uuid;
 
 pub fn configure(cfg: &mut ServiceConfig) {
-    cfg.service(wrap).service(unwrap).service(decode);
+    cfg.service(wrap)
+        .service(unwrap)
+        .service(decode)
+        .service(revoke)
+        .service(refresh)
+        .service(inspect)
+        .service(sign_claims)
+        .service(public_key_pem)
+        .service(public_jwks)
+        .service(list_keys)
+        .service(rotate_keys);
 }
 
-struct Keys {
-    encoding: EncodingKey,
-    decoding: DecodingKey,
+static CONFIG: Lazy<GiftConfig> = Lazy::new(|| config::load("gift"));
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct GiftConfig {
+    // lifetime of a gift token in seconds, retired keys keep verifying for as long
+    ttl: u64,
+    // longest lifetime a wrap request can ask for with `?ttl=`
+    max_ttl: u64,
+    // bearer token required to rotate keys, given directly or read from an environment variable,
+    // rotation is disabled when neither is set
+    rotate_token: Option<String>,
+    rotate_token_env: Option<String>,
+    keys: Vec<KeyConfig>,
+    // gifts can only be unwrapped once unless a wrap request says otherwise
+    single_use: bool,
+    // key set for /16/decode
+    jwks: JwksConfig,
+    // claims checked on gift tokens by /16/unwrap
+    claims: ClaimsPolicy,
+    // claims checked on tokens posted to /16/decode
+    decode: ClaimsPolicy,
+    encryption: EncryptionConfig,
+    cookie: CookieConfig,
+    refresh: RefreshConfig,
+    // private key for /16/sign
+    signer: SignerConfig,
+}
+
+impl Default for GiftConfig {
+    fn default() -> Self {
+        GiftConfig {
+            ttl: 3600,
+            max_ttl: 86400,
+            rotate_token: None,
+            rotate_token_env: None,
+            keys: Vec::new(),
+            single_use: false,
+            jwks: JwksConfig::default(),
+            claims: ClaimsPolicy::default(),
+            // Santa's tokens don't have to carry any claims
+            decode: ClaimsPolicy {
+                require_exp: false,
+                ..ClaimsPolicy::default()
+            },
+            encryption: EncryptionConfig::default(),
+            cookie: CookieConfig::default(),
+            refresh: RefreshConfig::default(),
+            signer: SignerConfig::default(),
+        }
+    }
+}
+
+impl GiftConfig {
+    // Longest time a token can stay valid, retired keys keep verifying for as long
+    fn max_lifetime(&self) -> u64 {
+        self.ttl.max(self.max_ttl).max(self.refresh.refresh_ttl)
+    }
 }
 
-impl Keys {
-    fn new(secret: &[u8]) -> Self {
-        Self {
-            encoding: EncodingKey::from_secret(secret),
-            decoding: DecodingKey::from_secret(secret),
+/// Access and refresh tokens issued by `/16/wrap?refresh=true`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct RefreshConfig {
+    // issue a refresh token unless a wrap request says otherwise
+    enabled: bool,
+    access_ttl: u64,
+    refresh_ttl: u64,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        RefreshConfig {
+            enabled: false,
+            access_ttl: 300,
+            refresh_ttl: 14 * 24 * 3600,
         }
     }
 }
 
-static KEYS: Lazy<Keys> = Lazy::new(|| {
-    let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 60);
-    Keys::new(secret.as_bytes())
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SameSiteConfig {
+    Strict,
+    Lax,
+    None,
+}
+
+/// Attributes of the gift cookie.
+#[derive(Deserialize)]
+#[serde(default)]
+struct CookieConfig {
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSiteConfig>,
+    path: Option<String>,
+    domain: Option<String>,
+    // Max-Age follows the token's exp so the cookie goes away with the token
+    max_age: bool,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            http_only: true,
+            secure: false,
+            same_site: Some(SameSiteConfig::Lax),
+            path: Some("/".to_string()),
+            domain: None,
+            max_age: true,
+        }
+    }
+}
+
+impl CookieConfig {
+    fn build(&self, name: &'static str, value: String, ttl: u64) -> Cookie<'static> {
+        let mut cookie = Cookie::new(name, value);
+        cookie.set_http_only(self.http_only);
+        cookie.set_secure(self.secure);
+        cookie.set_same_site(self.same_site.map(|same_site| match same_site {
+            SameSiteConfig::Strict => SameSite::Strict,
+            SameSiteConfig::Lax => SameSite::Lax,
+            SameSiteConfig::None => SameSite::None,
+        }));
+        if let Some(path) = &self.path {
+            cookie.set_path(path.clone());
+        }
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        if self.max_age {
+            cookie.set_max_age(Duration::seconds(ttl as i64));
+        }
+
+        cookie
+    }
+
+    // Same path and domain as the gift cookie, otherwise the browser keeps it
+    fn removal(&self, name: &'static str) -> Cookie<'static> {
+        let mut cookie = self.build(name, String::new(), 0);
+        cookie.make_removal();
+        cookie
+    }
+}
+
+static KEYS: Lazy<RwLock<KeyRing>> = Lazy::new(|| RwLock::new(KeyRing::new(&CONFIG.keys)));
+
+static ROTATE_TOKEN: Lazy<Option<String>> = Lazy::new(|| {
+    config::secret(
+        CONFIG.rotate_token.as_deref(),
+        CONFIG.rotate_token_env.as_deref(),
+    )
 });
 
+// Seconds before rotated keys are loaded again
+const KEY_SYNC_INTERVAL: u64 = 30;
+// An unknown kid only triggers a reload every few seconds
+const MIN_KEY_SYNC: u64 = 5;
+
+// Keys rotated on any instance, oldest first
+async fn load_keys(pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+    let stored = sqlx::query!(
+        "SELECT kid, secret, created_at, expires_at FROM gift_keys ORDER BY created_at"
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| StoredKey {
+        kid: row.kid,
+        secret: row.secret,
+        created_at: row.created_at.timestamp() as u64,
+        expires_at: row.expires_at.map(|exp| exp.timestamp() as u64),
+    })
+    .collect();
+
+    KEYS.write().unwrap().load(stored, CONFIG.max_lifetime());
+    Ok(())
+}
+
+// Reloads the rotated keys once they're stale or the kid is unknown,
+// a failed load keeps the keys loaded before
+async fn sync_keys(pool: &sqlx::PgPool, kid: Option<&str>) {
+    let fresh = {
+        let keys = KEYS.read().unwrap();
+        let known = kid.is_none_or(|kid| keys.verifying(Some(kid)).is_some());
+        keys.synced_within(KEY_SYNC_INTERVAL) && (known || keys.synced_within(MIN_KEY_SYNC))
+    };
+
+    if !fresh {
+        let _ = load_keys(pool).await;
+    }
+}
+
+static ENCRYPTER: Lazy<Encrypter> = Lazy::new(|| Encrypter::new(&CONFIG.encryption));
+
+static SIGNER: Lazy<Signer> = Lazy::new(|| Signer::new(&CONFIG.signer));
+
+// The signer's key is only trusted while signing is enabled
+fn signer() -> Option<&'static Signer> {
+    CONFIG.signer.enabled.then(|| &*SIGNER)
+}
+
+static JWKS: Lazy<JwksCache> = Lazy::new(|| JwksCache::new(&CONFIG.jwks));
+
+// Public key for /16/decode tokens without a kid or with one the JWKS doesn't have
+const SANTA_PEM: &str = include_str!("certs/day16_santa_public_key.pem");
+
+// Every algorithm the signer can issue is accepted too
+const DECODE_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
 #[derive(Debug, Serialize, Deserialize)]
 struct JwtClaims {
-    sub: String,             // Subject of the JWT, can represent the user or session
-    exp: usize,              // Expiration timestamp
+    sub: String, // Subject of the JWT, can represent the user or session
+    exp: usize,  // Expiration timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iat: Option<usize>, // Issued at timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nbf: Option<usize>, // Not valid before timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iss: Option<String>, // Issuer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>, // Audience
+    jti: Uuid,   // Unique id, used to revoke the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    family: Option<Uuid>, // Shared by the access and refresh tokens of one wrap
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    single_use: bool, // The gift can only be unwrapped once
     data: serde_json::Value, // Arbitrary JSON data
 }
 
+#[derive(Deserialize)]
+struct WrapQuery {
+    // lifetime of this gift in seconds
+    ttl: Option<u64>,
+    // seal the cookie as a JWE, the configured default when unset
+    encrypt: Option<bool>,
+    // the gift can only be unwrapped once, the configured default when unset
+    single_use: Option<bool>,
+    // issue an access and a refresh token, the configured default when unset
+    refresh: Option<bool>,
+}
+
+// Name of the cookie holding the refresh token
+const REFRESH_COOKIE: &str = "gift_refresh";
+
+impl JwtClaims {
+    fn new(sub: &str, ttl: u64, data: serde_json::Value) -> Self {
+        // set expiration to one token lifetime from now
+        let now = now() as usize;
+
+        JwtClaims {
+            sub: sub.to_string(),
+            exp: now + ttl as usize,
+            iat: Some(now),
+            nbf: Some(now),
+            iss: CONFIG.claims.issuer.clone(),
+            aud: CONFIG.claims.audience.first().cloned(),
+            jti: Uuid::new_v4(),
+            family: None,
+            single_use: false,
+            data,
+        }
+    }
+
+    // Signs the claims with the current key, sealed as a JWE when asked to
+    fn encode(&self, encrypt: bool) -> String {
+        let keys = KEYS.read().unwrap();
+        let key = keys.current();
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::default()
+        };
+        let token = jsonwebtoken::encode(&header, self, &key.keys.encoding).unwrap();
+
+        if encrypt {
+            return ENCRYPTER.seal(&token);
+        }
+
+        token
+    }
+}
+
+#[derive(Serialize)]
+struct TokenPair {
+    access_token: String,
+    refresh_token: String,
+    expires_in: u64,
+}
+
+// Access token in the gift cookie, refresh token in its own cookie and both in the body
+fn token_pair(access: &JwtClaims, renewal: &JwtClaims, encrypt: bool) -> HttpResponse {
+    let pair = TokenPair {
+        access_token: access.encode(encrypt),
+        refresh_token: renewal.encode(encrypt),
+        expires_in: CONFIG.refresh.access_ttl,
+    };
+
+    HttpResponse::Ok()
+        .cookie(
+            CONFIG
+                .cookie
+                .build("gift", pair.access_token.clone(), CONFIG.refresh.access_ttl),
+        )
+        .cookie(CONFIG.cookie.build(
+            REFRESH_COOKIE,
+            pair.refresh_token.clone(),
+            CONFIG.refresh.refresh_ttl,
+        ))
+        .json(pair)
+}
+
 #[post("/16/wrap")]
-pub async fn wrap(data: web::Json<serde_json::Value>) -> HttpResponse {
+pub async fn wrap(
+    data: web::Json<serde_json::Value>,
+    web::Query(query): web::Query<WrapQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    // Sign with the key rotated to last, whichever instance rotated it
+    sync_keys(pool.as_ref(), None).await;
+
     let data = data.into_inner();
+    let encrypt = query.encrypt.unwrap_or(CONFIG.encryption.enabled);
+
+    if query.refresh.unwrap_or(CONFIG.refresh.enabled) {
+        // The token pair has its own lifetimes and can't be single use
+        if query.ttl.is_some() || query.single_use.is_some() {
+            return HttpResponse::BadRequest().json(ClaimsError::new(
+                "refresh",
+                "ttl and single_use can't be combined with refresh tokens",
+            ));
+        }
 
-    // set expiration to one hour from not
-    let exp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as usize
-        + 3600;
+        // Both tokens belong to a new family, reusing a refresh token revokes all of it
+        let family = Some(Uuid::new_v4());
+        let mut access = JwtClaims::new("gift", CONFIG.refresh.access_ttl, data.clone());
+        access.family = family;
+        let mut renewal = JwtClaims::new("refresh", CONFIG.refresh.refresh_ttl, data);
+        renewal.family = family;
+
+        return token_pair(&access, &renewal, encrypt);
+    }
+
+    let ttl = query.ttl.unwrap_or(CONFIG.ttl);
+    if ttl == 0 || ttl > CONFIG.max_ttl {
+        return HttpResponse::BadRequest().json(ClaimsError::new(
+            "ttl",
+            format!("ttl must be between 1 and {} seconds", CONFIG.max_ttl),
+        ));
+    }
 
     // Create and encode JWT from claims
-    let cliams = JwtClaims {
-        sub: "gift".to_string(),
-        exp,
-        data,
-    };
-    let token = jsonwebtoken::encode(&Header::default(), &cliams, &KEYS.encoding).unwrap();
+    let mut cliams = JwtClaims::new("gift", ttl, data);
+    cliams.single_use = query.single_use.unwrap_or(CONFIG.single_use);
+    let token = cliams.encode(encrypt);
 
     // Create a Set-Cookie header
-    let cookie = Cookie::build("gift", token).finish();
+    let cookie = CONFIG.cookie.build("gift", token, ttl);
 
     HttpResponse::Ok().cookie(cookie).finish()
 }
 
 #[get("/16/unwrap")]
-pub async fn unwrap(req: HttpRequest) -> HttpResponse {
-    if req.headers().get(COOKIE).is_none() {
+pub async fn unwrap(req: HttpRequest, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    // The gift cookie, or a bearer token for clients without cookies
+    let Some(gift_token) = req
+        .cookie("gift")
+        .map(|cookie| cookie.value().to_string())
+        .or_else(|| bearer_token(&req).map(str::to_string))
+    else {
         return HttpResponse::BadRequest().finish();
+    };
+
+    let claims = match verify_gift(&gift_token, pool.as_ref()).await {
+        Ok(claims) => claims,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    // Refresh tokens only buy new access tokens
+    if claims.sub != "gift" {
+        return HttpResponse::BadRequest().json(ClaimsError::new("sub", "Not a gift token"));
     }
 
-    if let Some(gift_token) = req.cookie("gift") {
-        let token_data = jsonwebtoken::decode::<JwtClaims>(
-            gift_token.value(),
-            &KEYS.decoding,
-            &Validation::default(),
-        );
+    // Revoked gifts can't be unwrapped, single use gifts are consumed on the way
+    let revocation = if claims.single_use {
+        consume(&claims, pool.as_ref()).await
+    } else {
+        check_revoked(&claims, pool.as_ref()).await
+    };
 
-        match token_data {
-            Ok(claims) => {
-                let msg = claims.claims.data;
-                HttpResponse::Ok().json(msg)
+    match revocation {
+        Ok(()) => HttpResponse::Ok().json(claims.data),
+        Err(e) => e.into(),
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+// The token comes in the body, as a cookie or as a bearer token
+fn request_token(req: &HttpRequest, body: &str, cookie: &str) -> Option<String> {
+    match body.trim() {
+        "" => req
+            .cookie(cookie)
+            .map(|c| c.value().to_string())
+            .or(bearer_token(req).map(str::to_string)),
+        token => Some(token.to_string()),
+    }
+}
+
+async fn verify_gift(token: &str, pool: &sqlx::PgPool) -> Result<JwtClaims, ClaimsError> {
+    // Encrypted gifts are opened before they are verified
+    let token = if is_jwe(token) {
+        ENCRYPTER
+            .open(token)
+            .map_err(|e| ClaimsError::new("jwe", e))?
+    } else {
+        token.to_string()
+    };
+
+    let header = decode_header(&token)?;
+    sync_keys(pool, header.kid.as_deref()).await;
+
+    // The kid picks the key, a retired key verifies until it expires
+    let keys = KEYS.read().unwrap();
+    let Some(key) = keys.verifying(header.kid.as_deref()) else {
+        return Err(ClaimsError::new("kid", "Unknown or expired signing key"));
+    };
+
+    // gift tokens are always signed with HS256
+    let validation = CONFIG.claims.validation(Algorithm::HS256);
+    let claims =
+        jsonwebtoken::decode::<serde_json::Value>(&token, &key.keys.decoding, &validation)?.claims;
+    CONFIG.claims.check(&claims)?;
+
+    serde_json::from_value(claims).map_err(|e| ClaimsError::new("token", e.to_string()))
+}
+
+fn revoked_error(reason: &str) -> ClaimsError {
+    match reason {
+        "consumed" => ClaimsError::new("jti", "Gift has already been unwrapped"),
+        "refreshed" => ClaimsError::new("jti", "Refresh token has already been used"),
+        "reused" => ClaimsError::new("family", "Refresh token was reused, all tokens are revoked"),
+        _ => ClaimsError::new("jti", "Gift has been revoked"),
+    }
+}
+
+// A revoked token is the client's fault, a failing database isn't
+enum RevocationError {
+    Revoked(ClaimsError),
+    Storage,
+}
+
+impl From<sqlx::Error> for RevocationError {
+    fn from(_: sqlx::Error) -> Self {
+        RevocationError::Storage
+    }
+}
+
+impl From<RevocationError> for HttpResponse {
+    fn from(e: RevocationError) -> Self {
+        match e {
+            RevocationError::Revoked(e) => HttpResponse::BadRequest().json(e),
+            RevocationError::Storage => HttpResponse::InternalServerError().json(ClaimsError::new(
+                "storage",
+                "Revocations can't be checked right now",
+            )),
+        }
+    }
+}
+
+// Why the token, or its family, was revoked
+async fn revocation(
+    claims: &JwtClaims,
+    pool: &sqlx::PgPool,
+) -> Result<Option<String>, RevocationError> {
+    let ids = [Some(claims.jti), claims.family]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<Uuid>>();
+
+    sqlx::query_scalar!(
+        "SELECT reason FROM revoked_tokens WHERE jti = ANY($1) AND expires_at > CURRENT_TIMESTAMP ORDER BY reason = 'reused' DESC LIMIT 1",
+        &ids
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(RevocationError::from)
+}
+
+async fn check_revoked(claims: &JwtClaims, pool: &sqlx::PgPool) -> Result<(), RevocationError> {
+    match revocation(claims, pool).await? {
+        Some(reason) => Err(RevocationError::Revoked(revoked_error(&reason))),
+        None => Ok(()),
+    }
+}
+
+// Stores the id until `exp`, fails if it was already stored
+async fn store_revoked(
+    jti: Uuid,
+    reason: &str,
+    exp: usize,
+    pool: &sqlx::PgPool,
+) -> Result<bool, RevocationError> {
+    let expires_at = chrono::DateTime::from_timestamp(exp as i64, 0).unwrap_or_default();
+
+    // expired ids are dropped before new ones are added
+    sqlx::query!("DELETE FROM revoked_tokens WHERE expires_at <= CURRENT_TIMESTAMP")
+        .execute(pool)
+        .await?;
+
+    let stored = sqlx::query_scalar!(
+        "INSERT INTO revoked_tokens (jti, reason, expires_at) VALUES ($1, $2, $3) ON CONFLICT (jti) DO NOTHING RETURNING jti",
+        jti,
+        reason,
+        expires_at
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(stored.is_some())
+}
+
+async fn consume(claims: &JwtClaims, pool: &sqlx::PgPool) -> Result<(), RevocationError> {
+    if store_revoked(claims.jti, "consumed", claims.exp, pool).await? {
+        return Ok(());
+    }
+
+    // Already consumed or revoked, report which
+    check_revoked(claims, pool).await?;
+    Err(RevocationError::Revoked(revoked_error("consumed")))
+}
+
+#[post("/16/revoke")]
+pub async fn revoke(
+    req: HttpRequest,
+    token: String,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(token) = request_token(&req, &token, "gift") else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let cookie = req.cookie("gift");
+
+    let claims = match verify_gift(&token, pool.as_ref()).await {
+        Ok(claims) => claims,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    // Revoking twice is fine
+    if let Err(e) = store_revoked(claims.jti, "revoked", claims.exp, pool.as_ref()).await {
+        return e.into();
+    }
+
+    let mut response = HttpResponse::Ok();
+    if cookie.is_some_and(|c| c.value() == token) {
+        response.cookie(CONFIG.cookie.removal("gift"));
+    }
+
+    response.json(serde_json::json!({ "jti": claims.jti, "exp": claims.exp }))
+}
+
+#[post("/16/refresh")]
+pub async fn refresh(
+    req: HttpRequest,
+    token: String,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(token) = request_token(&req, &token, REFRESH_COOKIE) else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    let claims = match verify_gift(&token, pool.as_ref()).await {
+        Ok(claims) => claims,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let (Some(family), "refresh") = (claims.family, claims.sub.as_str()) else {
+        return HttpResponse::BadRequest().json(ClaimsError::new("sub", "Not a refresh token"));
+    };
+    let pool = pool.as_ref();
+
+    // Every refresh token is used once, an old one coming back means it leaked
+    let rotated = match revocation(&claims, pool).await {
+        Ok(None) => store_revoked(claims.jti, "refreshed", claims.exp, pool).await,
+        Ok(Some(reason)) if reason == "refreshed" => Ok(false),
+        Ok(Some(reason)) => return HttpResponse::Unauthorized().json(revoked_error(&reason)),
+        Err(e) => return e.into(),
+    };
+
+    match rotated {
+        Ok(true) => {}
+        Ok(false) => {
+            let exp = (now() + CONFIG.refresh.refresh_ttl) as usize;
+            if let Err(e) = store_revoked(family, "reused", exp, pool).await {
+                return e.into();
             }
-            Err(_) => HttpResponse::BadRequest().finish(),
+            return HttpResponse::Unauthorized().json(revoked_error("reused"));
+        }
+        Err(e) => return e.into(),
+    }
+
+    // New tokens keep the family and the data of the original wrap
+    let mut access = JwtClaims::new("gift", CONFIG.refresh.access_ttl, claims.data.clone());
+    access.family = Some(family);
+    let mut renewal = JwtClaims::new("refresh", CONFIG.refresh.refresh_ttl, claims.data);
+    renewal.family = Some(family);
+
+    token_pair(&access, &renewal, is_jwe(&token))
+}
+
+#[derive(Serialize)]
+struct VerifiedBy {
+    source: &'static str,
+    kid: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Expiry {
+    exp: u64,
+    expired: bool,
+    // seconds until the token expires, negative once it has
+    remaining: i64,
+}
+
+#[derive(Serialize)]
+struct Inspection {
+    encrypted: bool,
+    header: Header,
+    claims: serde_json::Value,
+    algorithm: Algorithm,
+    verified_by: Option<VerifiedBy>,
+    expiry: Option<Expiry>,
+    checks: Vec<Check>,
+    valid: bool,
+}
+
+// Only the signature, the claims are checked separately so every check gets reported
+fn signature_valid(token: &str, key: &DecodingKey, alg: Algorithm) -> bool {
+    let mut validation = Validation::new(alg);
+    validation.required_spec_claims.clear();
+    validation.validate_exp = false;
+    validation.validate_nbf = false;
+    validation.validate_aud = false;
+
+    jsonwebtoken::decode::<serde_json::Value>(token, key, &validation).is_ok()
+}
+
+// Tries the gift keys, the signer, the JWKS and Santa's key in that order,
+// with the same key and algorithm rules as /16/unwrap and /16/decode
+async fn verifying_key(token: &str, header: &Header, pool: &sqlx::PgPool) -> Option<VerifiedBy> {
+    let kid = header.kid.as_deref();
+    let verified_by = |source, kid: Option<&str>| {
+        Some(VerifiedBy {
+            source,
+            kid: kid.map(str::to_string),
+        })
+    };
+
+    // gift tokens are always signed with HS256
+    if header.alg == Algorithm::HS256 {
+        sync_keys(pool, kid).await;
+        let keys = KEYS.read().unwrap();
+        if let Some(key) = keys.verifying(kid) {
+            if signature_valid(token, &key.keys.decoding, header.alg) {
+                return verified_by("gift", Some(&key.kid));
+            }
+        }
+    }
+
+    if !DECODE_ALGORITHMS.contains(&header.alg) {
+        return None;
+    }
+
+    if let Some(signer) = signer().filter(|signer| kid == Some(signer.kid())) {
+        if signature_valid(token, &signer.decoding_key(), header.alg) {
+            return verified_by("signer", kid);
+        }
+    }
+
+    if let Some(kid) = kid.filter(|_| JWKS.is_configured()) {
+        if let Some(key) = JWKS
+            .key(kid)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|jwk| DecodingKey::from_jwk(&jwk).ok())
+        {
+            if signature_valid(token, &key, header.alg) {
+                return verified_by("jwks", Some(kid));
+            }
+        }
+    }
+
+    let santa = DecodingKey::from_rsa_pem(SANTA_PEM.as_bytes()).ok()?;
+    if signature_valid(token, &santa, header.alg) {
+        return verified_by("santa", None);
+    }
+
+    None
+}
+
+#[post("/16/inspect")]
+pub async fn inspect(
+    req: HttpRequest,
+    token: String,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(token) = request_token(&req, &token, "gift") else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    let encrypted = is_jwe(&token);
+    let token = if encrypted {
+        match ENCRYPTER.open(&token) {
+            Ok(token) => token,
+            Err(e) => return HttpResponse::BadRequest().json(ClaimsError::new("jwe", e)),
         }
     } else {
-        HttpResponse::BadRequest().finish()
+        token
+    };
+
+    let header = match decode_header(&token) {
+        Ok(header) => header,
+        Err(e) => return HttpResponse::BadRequest().json(ClaimsError::from(e)),
+    };
+    let Some(claims) = token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|payload| serde_json::from_slice::<serde_json::Value>(&payload).ok())
+    else {
+        return HttpResponse::BadRequest()
+            .json(ClaimsError::new("token", "Payload is not a JSON object"));
+    };
+
+    let verified_by = verifying_key(&token, &header, pool.as_ref()).await;
+
+    let mut checks = vec![match &verified_by {
+        Some(key) => Check::passed("signature", format!("Verified by the {} key", key.source)),
+        None => Check::failed("signature", "No configured key verifies the signature"),
+    }];
+
+    // Gift tokens follow the gift policy, anything else the decode policy
+    let policy = match &verified_by {
+        Some(VerifiedBy { source: "gift", .. }) => &CONFIG.claims,
+        _ => &CONFIG.decode,
+    };
+    checks.extend(policy.report(&claims));
+
+    // Revoked, consumed and refreshed gifts
+    if let Ok(gift) = serde_json::from_value::<JwtClaims>(claims.clone()) {
+        checks.push(match revocation(&gift, pool.as_ref()).await {
+            Ok(None) => Check::passed("jti", "Token hasn't been revoked"),
+            Ok(Some(reason)) => {
+                let e = revoked_error(&reason);
+                Check::failed(&e.check, e.message)
+            }
+            Err(_) => Check::failed("jti", "Revocations can't be checked right now"),
+        });
     }
+
+    let now = now();
+    let expiry = claims
+        .get("exp")
+        .and_then(serde_json::Value::as_u64)
+        .map(|exp| Expiry {
+            exp,
+            expired: exp <= now,
+            remaining: exp as i64 - now as i64,
+        });
+
+    HttpResponse::Ok().json(Inspection {
+        encrypted,
+        algorithm: header.alg,
+        header,
+        claims,
+        verified_by,
+        expiry,
+        valid: checks.iter().all(|c| c.passed),
+        checks,
+    })
 }
 
 #[post("/16/decode")]
 pub async fn decode(jwt: String) -> HttpResponse {
-    // decode claim
-    let pem = include_str!("certs/day16_santa_public_key.pem");
-    // let pem = fs::read("./src/certs/day16_santa_public_key.pem").expect("failed to read pem files");
-    let key = DecodingKey::from_rsa_pem(pem.as_bytes());
+    // return error for invalid header
+    let header = match decode_header(&jwt) {
+        Ok(header) => header,
+        Err(e) => return ClaimsError::from(e).into(),
+    };
+
+    if !DECODE_ALGORITHMS.contains(&header.alg) {
+        return ClaimsError::new("alg", format!("Unsupported algorithm {:?}", header.alg)).into();
+    }
+
+    // The kid picks the signer's key or a key from the JWKS, tokens without one use Santa's key
+    let key = match header.kid.as_deref() {
+        Some(kid) if signer().is_some_and(|signer| kid == signer.kid()) => {
+            Ok(SIGNER.decoding_key())
+        }
+        Some(kid) if JWKS.is_configured() => match JWKS.key(kid).await {
+            Ok(Some(jwk)) => DecodingKey::from_jwk(&jwk),
+            // Santa's tokens may carry a kid the set doesn't know
+            Ok(None) => DecodingKey::from_rsa_pem(SANTA_PEM.as_bytes()),
+            Err(e) => return HttpResponse::ServiceUnavailable().json(ClaimsError::new("jwks", e)),
+        },
+        _ => DecodingKey::from_rsa_pem(SANTA_PEM.as_bytes()),
+    };
 
     let Ok(key) = key else {
-        // could not create decoding key from PEM file
+        // could not create decoding key from the PEM file or JWK
         return HttpResponse::InternalServerError().finish();
     };
 
-    // return error for invalid header
-    let Ok(_header) = decode_header(&jwt) else {
-        println!("bad request line 101");
-        return HttpResponse::BadRequest().finish();
+    // configure validators
+    let validation = CONFIG.decode.validation(header.alg);
+
+    let claims = match jsonwebtoken::decode::<serde_json::Value>(&jwt, &key, &validation) {
+        Ok(token) => token.claims,
+        Err(e) => return ClaimsError::from(e).into(),
     };
 
-    // configure validators
-    let mut validation = Validation::default();
-    validation.validate_exp = true;
-    validation.algorithms = vec![Algorithm::RS256, Algorithm::RS512];
-    validation.set_required_spec_claims(&[""]);
-
-    let claims = jsonwebtoken::decode::<serde_json::Value>(&jwt, &key, &validation).map_err(|e| {
-        println!("Claims error: {:?}", e);
-        match e.kind() {
-            jsonwebtoken::errors::ErrorKind::InvalidSignature => {
-                HttpResponse::Unauthorized().finish()
-            }
-            _ => HttpResponse::BadRequest().finish(),
+    if let Err(e) = CONFIG.decode.check(&claims) {
+        return e.into();
+    }
+
+    HttpResponse::Ok().json(claims)
+}
+
+fn signing_disabled() -> HttpResponse {
+    HttpResponse::Forbidden().body("Signing is disabled")
+}
+
+#[derive(Deserialize)]
+struct SignQuery {
+    // the key's default algorithm when unset
+    alg: Option<Algorithm>,
+}
+
+#[post("/16/sign")]
+pub async fn sign_claims(
+    claims: web::Json<serde_json::Value>,
+    web::Query(query): web::Query<SignQuery>,
+) -> HttpResponse {
+    let Some(signer) = signer() else {
+        return signing_disabled();
+    };
+    let alg = query.alg.unwrap_or(signer.algorithms()[0]);
+
+    match signer.sign(alg, &claims) {
+        Ok(token) => HttpResponse::Ok().body(token),
+        Err(e) => HttpResponse::BadRequest().json(ClaimsError::new("alg", e)),
+    }
+}
+
+#[get("/16/public_key.pem")]
+pub async fn public_key_pem() -> HttpResponse {
+    let Some(signer) = signer() else {
+        return signing_disabled();
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/x-pem-file")
+        .body(signer.public_pem())
+}
+
+// Can be used as the JWKS of /16/decode
+#[get("/16/jwks.json")]
+pub async fn public_jwks() -> HttpResponse {
+    let Some(signer) = signer() else {
+        return signing_disabled();
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({ "keys": [signer.jwk()] }))
+}
+
+#[get("/16/keys")]
+pub async fn list_keys(pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    sync_keys(pool.as_ref(), None).await;
+
+    HttpResponse::Ok().json(KEYS.read().unwrap().status())
+}
+
+#[derive(Deserialize)]
+struct RotateRequest {
+    // a random kid and secret are generated when unset
+    kid: Option<String>,
+    secret: Option<String>,
+}
+
+// Retires the current keys and stores the new one, fails if the kid was used before
+async fn store_key(kid: &str, secret: &str, pool: &sqlx::PgPool) -> Result<bool, sqlx::Error> {
+    let retire_at = chrono::DateTime::from_timestamp((now() + CONFIG.max_lifetime()) as i64, 0)
+        .unwrap_or_default();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE gift_keys SET expires_at = $1 WHERE expires_at IS NULL",
+        retire_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let stored = sqlx::query_scalar!(
+        "INSERT INTO gift_keys (kid, secret) VALUES ($1, $2) ON CONFLICT (kid) DO NOTHING RETURNING kid",
+        kid,
+        secret
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if stored.is_none() {
+        return Ok(false);
+    }
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+#[post("/16/keys/rotate")]
+pub async fn rotate_keys(
+    req: HttpRequest,
+    body: web::Json<RotateRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(rotate_token) = ROTATE_TOKEN.as_deref() else {
+        return HttpResponse::Forbidden().body("Key rotation is disabled");
+    };
+
+    if bearer_token(&req) != Some(rotate_token) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    // Rotated keys are stored, so every instance verifies with them, even random ones
+    let RotateRequest { kid, secret } = body.into_inner();
+    let kid = kid.unwrap_or_else(random_kid);
+    let secret = secret.unwrap_or_else(random_secret);
+
+    if KEYS.read().unwrap().is_configured(&kid) {
+        return HttpResponse::Conflict().body(format!("Key \"{kid}\" already exists"));
+    }
+
+    let stored = match store_key(&kid, &secret, pool.as_ref()).await {
+        Ok(true) => load_keys(pool.as_ref()).await,
+        Ok(false) => {
+            return HttpResponse::Conflict().body(format!("Key \"{kid}\" already exists"));
         }
-    });
-    println!(" claims: {:#?}", claims);
-    match claims {
-        Ok(claims) => HttpResponse::Ok().json(claims.claims),
-        Err(err) => err,
+        Err(e) => Err(e),
+    };
+
+    match stored {
+        Ok(()) => HttpResponse::Ok().json(KEYS.read().unwrap().status()),
+        Err(_) => HttpResponse::InternalServerError().body("Keys can't be stored right now"),
     }
 }
@@ -1,17 +1,34 @@
-use std::{collections::HashMap, sync::LazyLock};
+use std::sync::LazyLock;
 
-use actix_web::{delete, get, post, put, web, HttpResponse};
-use rand::{distributions::Alphanumeric, Rng};
-use shuttle_runtime::tokio::sync::Mutex;
+use actix_web::{
+    delete, get,
+    http::header::{ETag, EntityTag, IfMatch, IfNoneMatch},
+    post, put, web, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
 
-static STATE: LazyLock<web::Data<State>> = LazyLock::new(Default::default);
+use crate::config;
+use crate::models::cursor::Cursors;
 
-#[derive(Default)]
-struct State {
-    tokens: Mutex<HashMap<String, i64>>,
+static CONFIG: LazyLock<QuotesConfig> = LazyLock::new(|| config::load("quotes"));
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct QuotesConfig {
+    page_size: i64,
+    max_page_size: i64,
+}
+
+impl Default for QuotesConfig {
+    fn default() -> Self {
+        QuotesConfig {
+            page_size: 3,
+            max_page_size: 50,
+        }
+    }
 }
 
-#[derive(serde::Serialize)]
+#[derive(Serialize)]
 struct Quote {
     id: uuid::Uuid,
     author: String,
@@ -20,21 +37,32 @@ struct Quote {
     version: i32,
 }
 
-pub fn configure(cfg: &mut web::ServiceConfig) {
-    let state = STATE.clone();
+#[derive(Serialize)]
+struct QuoteVersion {
+    quote_id: uuid::Uuid,
+    version: i32,
+    author: String,
+    quote: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
 
+pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(post_reset)
         .service(get_cite)
         .service(delete_remove)
         .service(put_undo)
         .service(post_draft)
         .service(get_list)
-        .app_data(state);
+        .service(get_search)
+        .service(get_history)
+        .service(get_version)
+        .service(post_revert)
+        .service(post_restore);
 }
 
 #[post("/19/reset")]
 async fn post_reset(pool: web::Data<sqlx::PgPool>) -> HttpResponse {
-    sqlx::query!("TRUNCATE quotes")
+    sqlx::query!("TRUNCATE quotes, quote_versions")
         .execute(pool.as_ref())
         .await
         .unwrap();
@@ -43,26 +71,51 @@ async fn post_reset(pool: web::Data<sqlx::PgPool>) -> HttpResponse {
 }
 
 #[get("/19/cite/{id}")]
-async fn get_cite(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+async fn get_cite(
+    path: web::Path<uuid::Uuid>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
     let uuid = path.into_inner();
 
-    let Ok(quote) = sqlx::query_as!(Quote, "SELECT * FROM quotes WHERE id = $1", uuid)
-        .fetch_one(pool.as_ref())
-        .await
+    let Ok(quote) = sqlx::query_as!(
+        Quote,
+        "SELECT id, author, quote, created_at, version FROM quotes WHERE id = $1 AND deleted_at IS NULL",
+        uuid
+    )
+    .fetch_one(pool.as_ref())
+    .await
     else {
         return HttpResponse::NotFound().finish();
     };
 
-    HttpResponse::Ok().json(quote)
+    let tag = etag(quote.version);
+    let unchanged = match if_none_match.map(web::Header::into_inner) {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&tag)),
+        None => false,
+    };
+    if unchanged {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(tag))
+            .finish();
+    }
+
+    HttpResponse::Ok().insert_header(ETag(tag)).json(quote)
 }
 
 #[delete("/19/remove/{id}")]
 async fn delete_remove(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
     let uuid = path.into_inner();
 
-    let Ok(quote) = sqlx::query_as!(Quote, "DELETE FROM quotes WHERE id = $1 RETURNING *", uuid)
-        .fetch_one(pool.as_ref())
-        .await
+    let Ok(quote) = sqlx::query_as!(
+        Quote,
+        "UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP WHERE id = $1 AND deleted_at IS NULL
+        RETURNING id, author, quote, created_at, version",
+        uuid
+    )
+    .fetch_one(pool.as_ref())
+    .await
     else {
         return HttpResponse::NotFound().finish();
     };
@@ -70,115 +123,409 @@ async fn delete_remove(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool
     HttpResponse::Ok().json(quote)
 }
 
-#[derive(serde::Deserialize)]
+#[derive(Deserialize)]
 struct Info {
     author: String,
     quote: String,
+    // expected version when there's no If-Match header
+    version: Option<i32>,
+}
+
+fn etag(version: i32) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+// The version a client expects to update
+enum Expected {
+    IfMatch(IfMatch),
+    Version(i32),
+}
+
+impl Expected {
+    fn new(
+        if_match: Option<web::Header<IfMatch>>,
+        version: Option<i32>,
+    ) -> Result<Self, VersionError> {
+        match (if_match, version) {
+            (Some(web::Header(if_match)), _) => Ok(Expected::IfMatch(if_match)),
+            (None, Some(version)) => Ok(Expected::Version(version)),
+            (None, None) => Err(VersionError::Missing),
+        }
+    }
+
+    fn matches(&self, version: i32) -> bool {
+        match self {
+            Expected::IfMatch(IfMatch::Any) => true,
+            Expected::IfMatch(IfMatch::Items(tags)) => {
+                tags.iter().any(|t| t.strong_eq(&etag(version)))
+            }
+            Expected::Version(expected) => *expected == version,
+        }
+    }
+
+    // Locks the quote until the transaction ends so the version can't change in between
+    async fn check(
+        &self,
+        uuid: uuid::Uuid,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), VersionError> {
+        let Some(version) = sqlx::query_scalar!(
+            "SELECT version FROM quotes WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            uuid
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .unwrap() else {
+            return Err(VersionError::NotFound);
+        };
+
+        if self.matches(version) {
+            return Ok(());
+        }
+
+        Err(VersionError::Stale {
+            version,
+            header: matches!(self, Expected::IfMatch(_)),
+        })
+    }
+}
+
+// A stale If-Match gets 412, a stale version in the body 409
+enum VersionError {
+    Missing,
+    NotFound,
+    Stale { version: i32, header: bool },
+}
+
+impl From<VersionError> for HttpResponse {
+    fn from(e: VersionError) -> Self {
+        match e {
+            VersionError::Missing => HttpResponse::PreconditionRequired()
+                .body("Updates need an If-Match header or a version"),
+            VersionError::NotFound => HttpResponse::NotFound().finish(),
+            VersionError::Stale { version, header } => {
+                let mut response = if header {
+                    HttpResponse::PreconditionFailed()
+                } else {
+                    HttpResponse::Conflict()
+                };
+
+                response
+                    .insert_header(ETag(etag(version)))
+                    .body(format!("Quote is at version {version}"))
+            }
+        }
+    }
 }
 
 #[put("/19/undo/{id}")]
 async fn put_undo(
     path: web::Path<uuid::Uuid>,
     info: web::Json<Info>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<sqlx::PgPool>,
 ) -> HttpResponse {
     let uuid = path.into_inner();
-    let Info { author, quote } = info.0;
+    let Info {
+        author,
+        quote,
+        version,
+    } = info.0;
+    let expected = match Expected::new(if_match, version) {
+        Ok(expected) => expected,
+        Err(e) => return e.into(),
+    };
 
-    let Ok(quote) = sqlx::query_as!(Quote,
-        "UPDATE quotes SET author = $2, quote = $3, version = version + 1 WHERE id = $1 RETURNING *",
+    let mut tx = pool.begin().await.unwrap();
+    if let Err(e) = expected.check(uuid, &mut tx).await {
+        return e.into();
+    }
+
+    let Ok(quote) = sqlx::query_as!(
+        Quote,
+        "UPDATE quotes SET author = $2, quote = $3, version = version + 1
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING id, author, quote, created_at, version",
         uuid,
         author,
         quote
     )
-    .fetch_one(pool.as_ref())
-    .await else
-    {
+    .fetch_one(&mut *tx)
+    .await
+    else {
         return HttpResponse::NotFound().finish();
     };
 
-    HttpResponse::Ok().json(quote)
+    record(&quote, &mut tx).await;
+    tx.commit().await.unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ETag(etag(quote.version)))
+        .json(quote)
 }
 
 #[post("/19/draft")]
 async fn post_draft(info: web::Json<Info>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
     let uuid = uuid::Uuid::new_v4();
-    let Info { author, quote } = info.0;
+    let Info { author, quote, .. } = info.0;
+
+    let mut tx = pool.begin().await.unwrap();
 
     let quote = sqlx::query_as!(
         Quote,
-        "INSERT INTO quotes(id, author, quote) VALUES($1, $2, $3) RETURNING *",
+        "INSERT INTO quotes(id, author, quote) VALUES($1, $2, $3) RETURNING id, author, quote, created_at, version",
         uuid,
         author,
         quote
     )
-    .fetch_one(pool.as_ref())
+    .fetch_one(&mut *tx)
     .await
     .unwrap();
 
-    HttpResponse::Created().json(quote)
+    record(&quote, &mut tx).await;
+    tx.commit().await.unwrap();
+
+    HttpResponse::Created()
+        .insert_header(ETag(etag(quote.version)))
+        .json(quote)
 }
 
-#[derive(serde::Deserialize)]
-struct Token {
-    token: Option<String>,
+// Every change to a quote's text is kept as a new revision
+async fn record(quote: &Quote, tx: &mut sqlx::Transaction<'_, sqlx::Postgres>) {
+    sqlx::query!(
+        "INSERT INTO quote_versions (quote_id, version, author, quote) VALUES ($1, $2, $3, $4)",
+        quote.id,
+        quote.version,
+        quote.author,
+        quote.quote
+    )
+    .execute(&mut **tx)
+    .await
+    .unwrap();
 }
 
-#[derive(serde::Serialize)]
+#[derive(Serialize)]
 struct List {
     quotes: Vec<Quote>,
     page: i64,
     next_token: Option<String>,
 }
 
+// Only the paginated endpoints need the cursor secret, the others keep working without it
+fn no_cursors() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().body("Pagination cursors are not configured")
+}
+
+// Requested page size, `None` when it's out of range
+fn page_size(size: Option<i64>) -> Option<i64> {
+    let size = size.unwrap_or(CONFIG.page_size);
+
+    (1..=CONFIG.max_page_size).contains(&size).then_some(size)
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    token: Option<String>,
+    author: Option<String>,
+    size: Option<i64>,
+}
+
+// Keyset cursor, pages continue after the last quote shown so inserts and deletes don't shift them
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListCursor {
+    // `created_at` and `id` of the last quote of the previous page
+    after: Option<(chrono::DateTime<chrono::Utc>, uuid::Uuid)>,
+    size: i64,
+    author: Option<String>,
+    page: i64,
+}
+
 #[get("/19/list")]
 async fn get_list(
-    web::Query(Token { token }): web::Query<Token>,
-    state: web::Data<State>,
+    web::Query(query): web::Query<ListQuery>,
+    cursors: Option<web::Data<Cursors>>,
     pool: web::Data<sqlx::PgPool>,
 ) -> HttpResponse {
-    let page = if let Some(token) = token {
-        let Some(page) = state.tokens.lock().await.remove(&token) else {
+    let Some(cursors) = cursors else {
+        return no_cursors();
+    };
+
+    // the token carries the page size and filters of the first request
+    let cursor = if let Some(token) = query.token {
+        let Some(cursor) = cursors.verify::<ListCursor>(&token) else {
             return HttpResponse::BadRequest().finish();
         };
 
-        page
+        cursor
     } else {
-        0
-    };
+        let Some(size) = page_size(query.size) else {
+            return HttpResponse::BadRequest().body("Invalid page size");
+        };
 
-    let count = sqlx::query!("SELECT COUNT(*) FROM quotes")
-        .fetch_one(pool.as_ref())
-        .await
-        .unwrap()
-        .count
-        .unwrap();
+        ListCursor {
+            after: None,
+            size,
+            author: query.author,
+            page: 0,
+        }
+    };
+    let (created_at, id) = cursor.after.unzip();
 
-    let quotes = sqlx::query_as!(
+    // one extra quote tells whether there's a next page
+    let mut quotes = sqlx::query_as!(
         Quote,
-        "SELECT * FROM quotes ORDER BY created_at LIMIT 3 OFFSET $1",
-        page * 3,
+        "SELECT id, author, quote, created_at, version FROM quotes
+        WHERE deleted_at IS NULL AND ($1::text IS NULL OR lower(author) = lower($1))
+            AND ($2::timestamptz IS NULL OR (created_at, id) > ($2::timestamptz, $3::uuid))
+        ORDER BY created_at, id
+        LIMIT $4",
+        cursor.author,
+        created_at,
+        id,
+        cursor.size + 1,
     )
     .fetch_all(pool.as_ref())
     .await
     .unwrap();
 
-    let page = page + 1;
+    let page = cursor.page + 1;
+
+    let next_token = if quotes.len() as i64 > cursor.size {
+        quotes.truncate(cursor.size as usize);
+        let last = quotes.last().unwrap();
+
+        Some(cursors.sign(&ListCursor {
+            after: Some((last.created_at, last.id)),
+            page,
+            ..cursor
+        }))
+    } else {
+        None
+    };
+
+    let list = List {
+        quotes,
+        page,
+        next_token,
+    };
+
+    HttpResponse::Ok().json(list)
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+    author: Option<String>,
+    token: Option<String>,
+    size: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SearchCursor {
+    // rank, `created_at` and `id` of the last match of the previous page
+    after: Option<(f32, chrono::DateTime<chrono::Utc>, uuid::Uuid)>,
+    size: i64,
+    q: String,
+    author: Option<String>,
+    page: i64,
+}
+
+struct Match {
+    id: uuid::Uuid,
+    author: String,
+    quote: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    version: i32,
+    rank: f32,
+}
+
+// `q` uses web search syntax: "quoted phrases", `or` and `-excluded` words
+#[get("/19/search")]
+async fn get_search(
+    web::Query(query): web::Query<SearchQuery>,
+    cursors: Option<web::Data<Cursors>>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(cursors) = cursors else {
+        return no_cursors();
+    };
+
+    let cursor = if let Some(token) = query.token {
+        let Some(cursor) = cursors.verify::<SearchCursor>(&token) else {
+            return HttpResponse::BadRequest().finish();
+        };
+
+        cursor
+    } else {
+        let Some(q) = query.q.filter(|q| !q.trim().is_empty()) else {
+            return HttpResponse::BadRequest().body("Missing search query");
+        };
+        let Some(size) = page_size(query.size) else {
+            return HttpResponse::BadRequest().body("Invalid page size");
+        };
+
+        SearchCursor {
+            after: None,
+            size,
+            q,
+            author: query.author,
+            page: 0,
+        }
+    };
+    let (rank, created_at, id) = match cursor.after {
+        Some((rank, created_at, id)) => (Some(rank), Some(created_at), Some(id)),
+        None => (None, None, None),
+    };
+
+    // best matches first, ties keep the order of /19/list
+    let mut matches = sqlx::query_as!(
+        Match,
+        r#"SELECT id, author, quote, created_at, version, ts_rank(search, query) AS "rank!"
+        FROM quotes, websearch_to_tsquery('english', $1) query
+        WHERE search @@ query AND deleted_at IS NULL AND ($2::text IS NULL OR lower(author) = lower($2))
+            AND ($3::real IS NULL OR ts_rank(search, query) < $3::real
+                OR (ts_rank(search, query) = $3::real AND (created_at, id) > ($4::timestamptz, $5::uuid)))
+        ORDER BY ts_rank(search, query) DESC, created_at, id
+        LIMIT $6"#,
+        cursor.q,
+        cursor.author,
+        rank,
+        created_at,
+        id,
+        cursor.size + 1,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .unwrap();
 
-    let next_token = if count > page * 3 {
-        let token: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
+    let page = cursor.page + 1;
 
-        state.tokens.lock().await.insert(token.clone(), page);
+    let next_token = if matches.len() as i64 > cursor.size {
+        matches.truncate(cursor.size as usize);
+        let last = matches.last().unwrap();
 
-        Some(token)
+        Some(cursors.sign(&SearchCursor {
+            after: Some((last.rank, last.created_at, last.id)),
+            page,
+            ..cursor
+        }))
     } else {
         None
     };
 
+    let quotes = matches
+        .into_iter()
+        .map(|m| Quote {
+            id: m.id,
+            author: m.author,
+            quote: m.quote,
+            created_at: m.created_at,
+            version: m.version,
+        })
+        .collect();
+
     let list = List {
         quotes,
         page,
@@ -187,3 +534,107 @@ async fn get_list(
 
     HttpResponse::Ok().json(list)
 }
+
+#[get("/19/history/{id}")]
+async fn get_history(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    let uuid = path.into_inner();
+
+    let versions = sqlx::query_as!(
+        QuoteVersion,
+        "SELECT quote_id, version, author, quote, created_at FROM quote_versions
+        WHERE quote_id = $1 ORDER BY version",
+        uuid
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .unwrap();
+
+    if versions.is_empty() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    HttpResponse::Ok().json(versions)
+}
+
+#[get("/19/history/{id}/{version}")]
+async fn get_version(
+    path: web::Path<(uuid::Uuid, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let (uuid, version) = path.into_inner();
+
+    let Ok(version) = sqlx::query_as!(
+        QuoteVersion,
+        "SELECT quote_id, version, author, quote, created_at FROM quote_versions
+        WHERE quote_id = $1 AND version = $2",
+        uuid,
+        version
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    HttpResponse::Ok().json(version)
+}
+
+// Reverting doesn't rewrite history, the old text comes back as a new revision
+#[post("/19/revert/{id}/{version}")]
+async fn post_revert(
+    path: web::Path<(uuid::Uuid, i32)>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let (uuid, version) = path.into_inner();
+    let expected = match Expected::new(if_match, None) {
+        Ok(expected) => expected,
+        Err(e) => return e.into(),
+    };
+
+    let mut tx = pool.begin().await.unwrap();
+    if let Err(e) = expected.check(uuid, &mut tx).await {
+        return e.into();
+    }
+
+    let Ok(quote) = sqlx::query_as!(
+        Quote,
+        "UPDATE quotes SET author = v.author, quote = v.quote, version = quotes.version + 1
+        FROM quote_versions v
+        WHERE quotes.id = $1 AND quotes.deleted_at IS NULL AND v.quote_id = quotes.id AND v.version = $2
+        RETURNING quotes.id, quotes.author, quotes.quote, quotes.created_at, quotes.version",
+        uuid,
+        version
+    )
+    .fetch_one(&mut *tx)
+    .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    record(&quote, &mut tx).await;
+    tx.commit().await.unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ETag(etag(quote.version)))
+        .json(quote)
+}
+
+#[post("/19/restore/{id}")]
+async fn post_restore(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    let uuid = path.into_inner();
+
+    let Ok(quote) = sqlx::query_as!(
+        Quote,
+        "UPDATE quotes SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL
+        RETURNING id, author, quote, created_at, version",
+        uuid
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    HttpResponse::Ok().json(quote)
+}
@@ -1,80 +1,290 @@
+use crate::config;
+use crate::models::lockfile::{LockFile, LockFileDiff};
+use crate::models::ornament::{
+    generate_ornament, render_png, Animation, MappingConfig, Ornament, Shape, CANVAS_SIZE,
+};
+use crate::models::palette::{CycleRules, Palette};
 use actix_multipart::Multipart;
 use actix_web::{
-    get, post,
+    get,
+    http::header::ACCEPT,
+    post, put,
     web::{self, ServiceConfig},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
+use askama::Template;
 use futures_util::StreamExt as _;
-use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+const PRESENTS: usize = 3;
+const ORNAMENTS: usize = 7;
+
+static CONFIG: Lazy<TreeConfig> = Lazy::new(|| config::load("tree"));
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct TreeConfig {
+    upload: UploadConfig,
+    palettes: Vec<Palette>,
+    // default cycle rules for presents, trees can override them
+    presents: CycleRules,
+    // how checksums turn into ornaments
+    ornaments: MappingConfig,
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        TreeConfig {
+            upload: UploadConfig::default(),
+            palettes: vec![Palette::classic()],
+            presents: CycleRules::default(),
+            ornaments: MappingConfig::default(),
+        }
+    }
+}
+
+impl TreeConfig {
+    fn palette(&self, name: &str) -> Option<&Palette> {
+        self.palettes.iter().find(|p| p.name == name)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct UploadConfig {
+    // Maximum size in bytes of all the files in one upload
+    max_size: usize,
+}
 
-const VALID_COLORS: [&str; 3] = ["red", "blue", "purple"];
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            max_size: 2 * 1024 * 1024,
+        }
+    }
+}
 
 pub fn configure(cfg: &mut ServiceConfig) {
     cfg.service(star)
         .service(preset)
         .service(ornament)
-        .service(lockfile);
+        .service(lockfile)
+        .service(lockfile_analyze)
+        .service(lockfile_diff)
+        .service(post_tree)
+        .service(get_tree)
+        .service(put_tree)
+        .service(share_tree);
+}
+
+// Render a template as an htmx fragment
+fn render<T: Template>(template: T) -> HttpResponse {
+    render_as(template, "text/html")
+}
+
+fn render_as<T: Template>(template: T, content_type: &str) -> HttpResponse {
+    match template.render() {
+        Ok(body) => HttpResponse::Ok().content_type(content_type).body(body),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
 }
 
-fn html_escape(input: &str) -> String {
-    input
-        .replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&#x27;")
+#[derive(serde::Serialize)]
+struct Tree {
+    id: uuid::Uuid,
+    star_lit: bool,
+    presents: Vec<String>,
+    ornaments: Vec<bool>,
+    checksums: Vec<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    palette: Option<String>,
+    cycle_order: Option<Vec<String>>,
+    random_cycle: Option<bool>,
+    skip_colors: Option<Vec<String>>,
 }
 
+impl Tree {
+    // Cycle rules of the tree, anything it doesn't set comes from the config
+    fn cycle_rules(&self) -> CycleRules {
+        let mut rules = CONFIG.presents.clone();
+
+        if let Some(palette) = &self.palette {
+            rules.palette = palette.clone();
+        }
+        if self.cycle_order.is_some() {
+            rules.order = self.cycle_order.clone();
+        }
+        if let Some(random) = self.random_cycle {
+            rules.random = random;
+        }
+        if let Some(skip) = &self.skip_colors {
+            rules.skip = skip.clone();
+        }
+
+        rules
+    }
+}
+
+// Fragments given a `tree` read and write the state of that tree
+#[derive(Deserialize)]
+struct TreeQuery {
+    tree: Option<uuid::Uuid>,
+    // position of the present on the tree, starting at 1
+    present: Option<usize>,
+}
+
+impl TreeQuery {
+    // Query string that keeps the follow-up requests on the same tree
+    fn to_query(&self) -> String {
+        match (self.tree, self.present) {
+            (Some(tree), Some(present)) => format!("?tree={tree}&present={present}"),
+            (Some(tree), None) => format!("?tree={tree}"),
+            (None, _) => String::new(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "star.html")]
+struct StarTemplate;
+
 #[get("/23/star")]
-pub async fn star() -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type("text/html")
-        .body(r#"<div id="star" class="lit"></div>"#)
+pub async fn star(
+    web::Query(query): web::Query<TreeQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    if let Some(id) = query.tree {
+        let Ok(_) = sqlx::query!(
+            "UPDATE trees SET star_lit = true, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING id",
+            id
+        )
+        .fetch_one(pool.as_ref())
+        .await
+        else {
+            return HttpResponse::NotFound().finish();
+        };
+    }
+
+    render(StarTemplate)
+}
+
+#[derive(Template)]
+#[template(path = "present.html")]
+struct PresentTemplate<'a> {
+    color: &'a str,
+    hex: &'a str,
+    next_color: &'a str,
+    query: &'a str,
 }
 
 #[get("/23/present/{color}")]
-pub async fn preset(color: web::Path<String>) -> HttpResponse {
-    let mut color = color.into_inner();
-    color = html_escape(&color);
+pub async fn preset(
+    color: web::Path<String>,
+    web::Query(query): web::Query<TreeQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let color = color.into_inner();
 
-    if !VALID_COLORS.contains(&color.as_str()) {
-        return HttpResponse::ImATeapot().finish();
-    }
+    let tree = match query.tree {
+        Some(id) => {
+            let Ok(tree) = sqlx::query_as!(Tree, "SELECT * FROM trees WHERE id = $1", id)
+                .fetch_one(pool.as_ref())
+                .await
+            else {
+                return HttpResponse::NotFound().finish();
+            };
+
+            Some(tree)
+        }
+        None => None,
+    };
+
+    let rules = tree
+        .as_ref()
+        .map(Tree::cycle_rules)
+        .unwrap_or_else(|| CONFIG.presents.clone());
+    let Some(palette) = CONFIG.palette(&rules.palette) else {
+        return HttpResponse::InternalServerError().finish();
+    };
 
     // determine the next color in the cycle
-    let next_color = {
-        let current_index = VALID_COLORS.iter().position(|&c| c == color).unwrap();
-        VALID_COLORS[(current_index + 1) % VALID_COLORS.len()]
+    let (Some(current), Some(next)) = (palette.get(&color), rules.next(palette, &color)) else {
+        return HttpResponse::ImATeapot().body(palette.unknown_color(&color));
     };
 
+    if let Some(id) = query.tree {
+        let Some(idx) = query.present.filter(|p| (1..=PRESENTS).contains(p)) else {
+            return HttpResponse::BadRequest().finish();
+        };
+
+        let Ok(_) = sqlx::query!(
+            "UPDATE trees SET presents[$2] = $3, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING id",
+            id,
+            idx as i32,
+            color
+        )
+        .fetch_one(pool.as_ref())
+        .await
+        else {
+            return HttpResponse::NotFound().finish();
+        };
+    }
+
     // Generate the HTML with the new color and hx-get attribute
-    let html = format!(
-        r#"
-            <div class="present {color}" hx-get="/23/present/{next_color}" hx-swap="outerHTML">
-                <div class="ribbon"></div>
-                <div class="ribbon"></div>
-                <div class="ribbon"></div>
-                <div class="ribbon"></div>
-            </div>
-        "#,
-        color = color,
-        next_color = next_color
-    );
-
-    HttpResponse::Ok().content_type("text/html").body(html)
+    render(PresentTemplate {
+        color: &color,
+        hex: &current.hex,
+        next_color: &next.name,
+        query: &query.to_query(),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "ornament.html")]
+struct OrnamentTemplate<'a> {
+    class: &'a str,
+    n: &'a str,
+    next_state: &'a str,
+    query: &'a str,
 }
 
 #[get("/23/ornament/{state}/{n}")]
-pub async fn ornament(info: web::Path<(String, String)>) -> HttpResponse {
-    let (mut state, mut n) = info.into_inner();
-    state = html_escape(&state);
-    n = html_escape(&n);
+pub async fn ornament(
+    info: web::Path<(String, String)>,
+    web::Query(query): web::Query<TreeQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let (state, n) = info.into_inner();
 
     // checking for valid state
     if state != "on" && state != "off" {
         return HttpResponse::ImATeapot().finish();
     }
 
+    if let Some(id) = query.tree {
+        let Some(idx) = n
+            .parse::<usize>()
+            .ok()
+            .filter(|n| (1..=ORNAMENTS).contains(n))
+        else {
+            return HttpResponse::BadRequest().finish();
+        };
+
+        let Ok(_) = sqlx::query!(
+            "UPDATE trees SET ornaments[$2] = $3, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING id",
+            id,
+            idx as i32,
+            state == "on"
+        )
+        .fetch_one(pool.as_ref())
+        .await
+        else {
+            return HttpResponse::NotFound().finish();
+        };
+    }
+
     // Determining the next state, and class
     let next_state = if state == "on" { "off" } else { "on" };
     let class = if state == "on" {
@@ -84,97 +294,463 @@ pub async fn ornament(info: web::Path<(String, String)>) -> HttpResponse {
     };
 
     // Generate the HTML
-    let html = format!(
-        r#"
-            <div class="{class}" id="ornament{n}" hx-trigger="load delay:2s once" hx-get="/23/ornament/{next_state}/{n}" hx-swap="outerHTML"></div>
-        "#,
-        n = n,
-        class = class,
-        next_state = next_state
-    );
-
-    HttpResponse::Ok().content_type("text/html").body(html)
+    render(OrnamentTemplate {
+        class,
+        n: &n,
+        next_state,
+        query: &query.to_query(),
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Package {
-    checksum: Option<String>,
+enum UploadError {
+    Malformed,
+    MissingField,
+    InvalidUtf8,
+    TooLarge,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct LockFile {
-    package: Vec<Package>,
+impl From<UploadError> for HttpResponse {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::Malformed => {
+                HttpResponse::BadRequest().body("Malformed multipart payload")
+            }
+            UploadError::MissingField => HttpResponse::BadRequest().body("Missing lockfile field"),
+            UploadError::InvalidUtf8 => {
+                HttpResponse::BadRequest().body("Lockfile is not valid UTF-8")
+            }
+            UploadError::TooLarge => HttpResponse::PayloadTooLarge().finish(),
+        }
+    }
 }
 
-#[post("/23/lockfile")]
-pub async fn lockfile(mut payload: Multipart) -> HttpResponse {
-    // Extract data from the payload
-    let mut data = String::new();
+// Extract the named fields from the multipart payload, in the order they were sent.
+// A name can appear more than once, the size of all fields together is capped.
+async fn read_fields(
+    mut payload: Multipart,
+    names: &[&str],
+) -> Result<Vec<(String, String)>, UploadError> {
+    let max_size = CONFIG.upload.max_size;
+    let mut size = 0;
+    let mut fields = Vec::new();
+
     while let Some(field) = payload.next().await {
-        let mut field = match field {
-            Ok(field) => field,
-            Err(_) => return HttpResponse::BadRequest().finish(),
-        };
+        let mut field = field.map_err(|_| UploadError::Malformed)?;
 
         // checking name
-        if field.name().unwrap() == "lockfile" {
-            // Add file to the container
-            while let Some(chunk) = field.next().await {
-                let chunk = match chunk {
-                    Ok(chunk) => chunk,
-                    Err(_) => return HttpResponse::BadRequest().finish(),
-                };
-
-                data.push_str(&String::from_utf8_lossy(&chunk));
+        let Some(name) = field.name().map(str::to_string) else {
+            return Err(UploadError::Malformed);
+        };
+        if !names.contains(&name.as_str()) {
+            continue;
+        }
+
+        // Add file to the container, stopping as soon as the limit is hit
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| UploadError::Malformed)?;
+
+            size += chunk.len();
+            if size > max_size {
+                return Err(UploadError::TooLarge);
             }
+
+            data.extend_from_slice(&chunk);
         }
+
+        let data = String::from_utf8(data).map_err(|_| UploadError::InvalidUtf8)?;
+        fields.push((name, data));
     }
 
-    // Convert file to valid cargo manifest
-    let lockfile = match toml::from_str::<LockFile>(&data) {
-        Ok(lockfile) => lockfile,
-        Err(_) => {
-            return HttpResponse::BadRequest().finish();
-        }
+    Ok(fields)
+}
+
+async fn read_lockfiles(payload: Multipart) -> Result<Vec<String>, UploadError> {
+    let lockfiles = read_fields(payload, &["lockfile"])
+        .await?
+        .into_iter()
+        .map(|(_, data)| data)
+        .collect::<Vec<String>>();
+
+    if lockfiles.is_empty() {
+        return Err(UploadError::MissingField);
+    }
+
+    Ok(lockfiles)
+}
+
+#[derive(Template)]
+#[template(path = "lockfile.html")]
+struct LockfileTemplate {
+    lockfiles: Vec<Vec<Ornament>>,
+    // every lockfile gets its own container when several are uploaded
+    wrap: bool,
+}
+
+#[derive(Template)]
+#[template(path = "lockfile.svg")]
+struct LockfileSvgTemplate {
+    lockfiles: Vec<Vec<Ornament>>,
+    width: u32,
+    height: u32,
+    size: usize,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LockfileFormat {
+    #[default]
+    Html,
+    Svg,
+    Png,
+}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    #[serde(default)]
+    format: LockfileFormat,
+}
+
+#[post("/23/lockfile")]
+pub async fn lockfile(
+    payload: Multipart,
+    web::Query(query): web::Query<TreeQuery>,
+    web::Query(FormatQuery { format }): web::Query<FormatQuery>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let lockfiles = match read_lockfiles(payload).await {
+        Ok(lockfiles) => lockfiles,
+        Err(e) => return e.into(),
     };
 
-    let mut html = String::new();
+    let wrap = lockfiles.len() > 1;
+    let mut ornaments = Vec::new();
+    let mut checksums = Vec::new();
 
-    for package in lockfile.package {
-        if let Some(checksum) = package.checksum {
-            match generate_div(&checksum) {
-                Ok(div) => {
-                    html.push_str(&div);
+    for data in &lockfiles {
+        // Convert file to valid cargo manifest
+        let lockfile = match toml::from_str::<LockFile>(data) {
+            Ok(lockfile) => lockfile,
+            Err(_) => {
+                return HttpResponse::BadRequest().finish();
+            }
+        };
+
+        let mut lockfile_ornaments = Vec::new();
+        for package in lockfile.package {
+            if let Some(checksum) = package.checksum {
+                match generate_ornament(&checksum, CONFIG.ornaments.mapping()) {
+                    Ok(decoration) => {
+                        lockfile_ornaments.push(decoration);
+                        checksums.push(checksum);
+                    }
+                    Err(_) => return HttpResponse::UnprocessableEntity().finish(),
                 }
-                Err(_) => return HttpResponse::UnprocessableEntity().finish(),
+            } else {
+                // skip the current iteration
+                continue;
             }
-        } else {
-            // skip the current iteration
-            continue;
         }
+
+        ornaments.push(lockfile_ornaments);
+    }
+
+    // The tree keeps the checksums, its ornaments are drawn from them
+    if let Some(id) = query.tree {
+        let Ok(_) = sqlx::query!(
+            "UPDATE trees SET checksums = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING id",
+            id,
+            &checksums
+        )
+        .fetch_one(pool.as_ref())
+        .await
+        else {
+            return HttpResponse::NotFound().finish();
+        };
+    }
+
+    match format {
+        LockfileFormat::Html => render(LockfileTemplate {
+            lockfiles: ornaments,
+            wrap,
+        }),
+        // Images put the canvas of every lockfile side by side
+        LockfileFormat::Svg => render_as(
+            LockfileSvgTemplate {
+                width: CANVAS_SIZE * ornaments.len() as u32,
+                height: CANVAS_SIZE,
+                size: CANVAS_SIZE as usize,
+                lockfiles: ornaments,
+            },
+            "image/svg+xml",
+        ),
+        LockfileFormat::Png => match render_png(&ornaments) {
+            Some(png) => HttpResponse::Ok().content_type("image/png").body(png),
+            None => HttpResponse::InternalServerError().finish(),
+        },
     }
+}
+
+#[post("/23/lockfile/analyze")]
+pub async fn lockfile_analyze(payload: Multipart) -> HttpResponse {
+    let lockfiles = match read_lockfiles(payload).await {
+        Ok(lockfiles) => lockfiles,
+        Err(e) => return e.into(),
+    };
+
+    // Only one lockfile can be analyzed at a time
+    let [data] = lockfiles.as_slice() else {
+        return HttpResponse::BadRequest().body("Expected a single lockfile");
+    };
 
-    HttpResponse::Ok().content_type("text/html").body(html)
+    let Ok(parsed) = toml::from_str::<LockFile>(data) else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    HttpResponse::Ok().json(parsed.analyze())
 }
 
-fn generate_div(checksum: &str) -> Result<String, String> {
-    // Check cases
-    if checksum.len() < 10
-        || checksum.chars().any(|c| {
-            let c = c.to_ascii_lowercase();
+// htmx requests and browsers get an HTML fragment, everyone else gets JSON
+fn wants_html(req: &HttpRequest) -> bool {
+    req.headers().contains_key("HX-Request")
+        || req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|a| a.to_str().ok())
+            .is_some_and(|a| a.contains("text/html"))
+}
 
-            !('0'..='f').contains(&c) || (c > '9' && c < 'a')
-        })
+#[derive(Template)]
+#[template(path = "lockfile_diff.html")]
+struct LockfileDiffTemplate<'a> {
+    diff: &'a LockFileDiff,
+}
+
+#[post("/23/lockfile/diff")]
+pub async fn lockfile_diff(req: HttpRequest, payload: Multipart) -> HttpResponse {
+    let fields = match read_fields(payload, &["old", "new"]).await {
+        Ok(fields) => fields,
+        Err(e) => return e.into(),
+    };
+
+    // Both lockfiles are required, exactly once
+    let (old, new): (Vec<_>, Vec<_>) = fields.into_iter().partition(|(name, _)| name == "old");
+    let ([(_, old)], [(_, new)]) = (old.as_slice(), new.as_slice()) else {
+        return HttpResponse::from(UploadError::MissingField);
+    };
+
+    let (Ok(old), Ok(new)) = (
+        toml::from_str::<LockFile>(old),
+        toml::from_str::<LockFile>(new),
+    ) else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    let diff = old.diff(&new);
+
+    if wants_html(&req) {
+        render(LockfileDiffTemplate { diff: &diff })
+    } else {
+        HttpResponse::Ok().json(diff)
+    }
+}
+
+#[post("/23/tree")]
+async fn post_tree(pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    let uuid = uuid::Uuid::new_v4();
+
+    let tree = sqlx::query_as!(Tree, "INSERT INTO trees(id) VALUES($1) RETURNING *", uuid)
+        .fetch_one(pool.as_ref())
+        .await
+        .unwrap();
+
+    HttpResponse::Created().json(tree)
+}
+
+#[get("/23/tree/{id}")]
+async fn get_tree(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    let uuid = path.into_inner();
+
+    let Ok(tree) = sqlx::query_as!(Tree, "SELECT * FROM trees WHERE id = $1", uuid)
+        .fetch_one(pool.as_ref())
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    HttpResponse::Ok().json(tree)
+}
+
+#[derive(Deserialize)]
+struct TreeUpdate {
+    star_lit: Option<bool>,
+    presents: Option<Vec<String>>,
+    ornaments: Option<Vec<bool>>,
+    palette: Option<String>,
+    cycle_order: Option<Vec<String>>,
+    random_cycle: Option<bool>,
+    skip_colors: Option<Vec<String>>,
+}
+
+#[put("/23/tree/{id}")]
+async fn put_tree(
+    path: web::Path<uuid::Uuid>,
+    info: web::Json<TreeUpdate>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let uuid = path.into_inner();
+    let update = info.0;
+
+    let Ok(tree) = sqlx::query_as!(Tree, "SELECT * FROM trees WHERE id = $1", uuid)
+        .fetch_one(pool.as_ref())
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    // Colors are checked against the palette the tree ends up with
+    let mut rules = tree.cycle_rules();
+    if let Some(palette) = &update.palette {
+        rules.palette = palette.clone();
+    }
+    let Some(palette) = CONFIG.palette(&rules.palette) else {
+        let valid = CONFIG
+            .palettes
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        return HttpResponse::BadRequest().body(format!(
+            "Unknown palette \"{}\", valid palettes are: {valid}",
+            rules.palette
+        ));
+    };
+
+    let colors = [
+        update.presents.as_deref(),
+        update.cycle_order.as_deref(),
+        update.skip_colors.as_deref(),
+    ];
+    let presents = update.presents.as_deref().unwrap_or(&tree.presents);
+    if let Some(unknown) = colors
+        .into_iter()
+        .flatten()
+        .flatten()
+        .chain(presents)
+        .find(|c| palette.get(c).is_none())
+    {
+        return HttpResponse::BadRequest().body(palette.unknown_color(unknown));
+    }
+
+    // The tree always has the same number of presents and ornaments
+    if update
+        .presents
+        .as_ref()
+        .is_some_and(|p| p.len() != PRESENTS)
+        || update
+            .ornaments
+            .as_ref()
+            .is_some_and(|o| o.len() != ORNAMENTS)
     {
-        return Err(format!("Invalid Checksum: {}", checksum));
+        return HttpResponse::BadRequest().finish();
     }
 
-    let color = &checksum[0..6];
-    u32::from_str_radix(color, 16).map_err(|e| e.to_string())?;
-    let top = u8::from_str_radix(&checksum[6..8], 16).map_err(|e| e.to_string())?;
-    let left = u8::from_str_radix(&checksum[8..10], 16).map_err(|e| e.to_string())?;
+    let Ok(tree) = sqlx::query_as!(Tree,
+        "UPDATE trees SET star_lit = COALESCE($2, star_lit), presents = COALESCE($3, presents), ornaments = COALESCE($4, ornaments), palette = COALESCE($5, palette), cycle_order = COALESCE($6, cycle_order), random_cycle = COALESCE($7, random_cycle), skip_colors = COALESCE($8, skip_colors), updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING *",
+        uuid,
+        update.star_lit,
+        update.presents.as_deref(),
+        update.ornaments.as_deref(),
+        update.palette,
+        update.cycle_order.as_deref(),
+        update.random_cycle,
+        update.skip_colors.as_deref()
+    )
+    .fetch_one(pool.as_ref())
+    .await else
+    {
+        return HttpResponse::NotFound().finish();
+    };
+
+    HttpResponse::Ok().json(tree)
+}
+
+#[derive(Template)]
+#[template(path = "tree.html")]
+struct TreeTemplate<'a> {
+    star_lit: bool,
+    presents: Vec<PresentTemplate<'a>>,
+    ornaments: Vec<OrnamentTemplate<'a>>,
+    lockfiles: Vec<Vec<Ornament>>,
+    wrap: bool,
+    query: &'a str,
+}
+
+// The full tree page for a stored tree, which can be shared by its link
+#[get("/23/tree/{id}/share")]
+async fn share_tree(path: web::Path<uuid::Uuid>, pool: web::Data<sqlx::PgPool>) -> HttpResponse {
+    let uuid = path.into_inner();
+
+    let Ok(tree) = sqlx::query_as!(Tree, "SELECT * FROM trees WHERE id = $1", uuid)
+        .fetch_one(pool.as_ref())
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let query = format!("?tree={}", tree.id);
+    let present_queries = (1..=tree.presents.len())
+        .map(|idx| format!("{query}&present={idx}"))
+        .collect::<Vec<String>>();
+    let ns = (1..=tree.ornaments.len())
+        .map(|n| n.to_string())
+        .collect::<Vec<String>>();
+
+    let rules = tree.cycle_rules();
+    let Some(palette) = CONFIG.palette(&rules.palette) else {
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    let mut presents = Vec::new();
+    for (color, query) in tree.presents.iter().zip(&present_queries) {
+        let (Some(current), Some(next)) = (palette.get(color), rules.next(palette, color)) else {
+            return HttpResponse::InternalServerError().finish();
+        };
+
+        presents.push(PresentTemplate {
+            color,
+            hex: &current.hex,
+            next_color: &next.name,
+            query,
+        });
+    }
+
+    let ornaments = tree
+        .ornaments
+        .iter()
+        .zip(&ns)
+        .map(|(&on, n)| OrnamentTemplate {
+            class: if on { "ornament on" } else { "ornament" },
+            n,
+            next_state: if on { "off" } else { "on" },
+            query: &query,
+        })
+        .collect();
+
+    // Stored checksums were valid when they were saved
+    let decorations = tree
+        .checksums
+        .iter()
+        .filter_map(|c| generate_ornament(c, CONFIG.ornaments.mapping()).ok())
+        .collect();
 
-    Ok(format!(
-        r#"<div style="background-color:#{color};top:{top}px;left:{left}px;"></div>"#
-    ))
+    render(TreeTemplate {
+        star_lit: tree.star_lit,
+        presents,
+        ornaments,
+        lockfiles: vec![decorations],
+        wrap: false,
+        query: &query,
+    })
 }
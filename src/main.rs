@@ -1,3 +1,4 @@
+pub mod config;
 pub mod day_02;
 pub mod day_05;
 pub mod day_09;
@@ -15,7 +16,9 @@ use actix_web::{
     HttpResponse, Responder,
 };
 use models::board::Board;
+use models::cursor::{Cursors, CURSOR_SECRET};
 use shuttle_actix_web::ShuttleActixWeb;
+use shuttle_runtime::SecretStore;
 use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 
@@ -32,6 +35,7 @@ async fn seek() -> impl Responder {
 #[shuttle_runtime::main]
 async fn main(
     #[shuttle_shared_db::Postgres] pool: sqlx::PgPool,
+    #[shuttle_runtime::Secrets] secrets: SecretStore,
 ) -> ShuttleActixWeb<impl FnOnce(&mut ServiceConfig) + Send + Clone + 'static> {
     // migrating database
     sqlx::migrate!()
@@ -44,11 +48,20 @@ async fn main(
     let bucket = Arc::new(Mutex::new(limiter));
     // Setting up board
     let grid = Arc::new(RwLock::new(Board::new()));
+    // Day 19 pagination answers 503 without the cursor secret, every other route still works
+    let cursors = secrets
+        .get(CURSOR_SECRET)
+        .map(|secret| Data::new(Cursors::new(&secret)));
 
     let config = move |cfg: &mut ServiceConfig| {
         cfg.app_data(Data::new(bucket.clone()))
             .app_data(Data::new(grid.clone()))
             .app_data(Data::new(pool))
+            .configure(|cfg| {
+                if let Some(cursors) = cursors {
+                    cfg.app_data(cursors);
+                }
+            })
             .service(hello_world)
             .service(seek)
             .service(Files::new("/assets", "assets"))
@@ -0,0 +1,231 @@
+use crate::models::keyring::now;
+use actix_web::HttpResponse;
+use jsonwebtoken::{errors::ErrorKind, Algorithm, Validation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Rules a token's claims have to follow on top of its signature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClaimsPolicy {
+    // expected `iss`, not checked when unset
+    pub issuer: Option<String>,
+    // accepted `aud` values, not checked when empty
+    pub audience: Vec<String>,
+    // seconds of clock skew allowed on `exp`, `nbf` and `iat`
+    pub leeway: u64,
+    pub require_exp: bool,
+    pub validate_nbf: bool,
+    pub require_iat: bool,
+    // tokens issued longer ago than this many seconds are rejected
+    pub max_age: Option<u64>,
+    // custom claims that must be present
+    pub required: Vec<String>,
+}
+
+impl Default for ClaimsPolicy {
+    fn default() -> Self {
+        ClaimsPolicy {
+            issuer: None,
+            audience: Vec::new(),
+            leeway: 60,
+            require_exp: true,
+            validate_nbf: true,
+            require_iat: false,
+            max_age: None,
+            required: Vec::new(),
+        }
+    }
+}
+
+/// A failed validation check, returned as a JSON body naming the check.
+#[derive(Debug, Serialize)]
+pub struct ClaimsError {
+    pub check: String,
+    pub message: String,
+}
+
+impl ClaimsError {
+    pub fn new(check: &str, message: impl Into<String>) -> Self {
+        ClaimsError {
+            check: check.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn is_signature(&self) -> bool {
+        self.check == "signature"
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ClaimsError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        let check = match e.kind() {
+            ErrorKind::InvalidSignature => "signature",
+            ErrorKind::InvalidAlgorithm | ErrorKind::MissingAlgorithm => "alg",
+            ErrorKind::ExpiredSignature => "exp",
+            ErrorKind::ImmatureSignature => "nbf",
+            ErrorKind::InvalidIssuer => "iss",
+            ErrorKind::InvalidAudience => "aud",
+            ErrorKind::InvalidSubject => "sub",
+            ErrorKind::MissingRequiredClaim(claim) => claim,
+            _ => "token",
+        };
+
+        ClaimsError::new(check, e.to_string())
+    }
+}
+
+impl From<ClaimsError> for HttpResponse {
+    fn from(e: ClaimsError) -> Self {
+        if e.is_signature() {
+            return HttpResponse::Unauthorized().json(e);
+        }
+
+        HttpResponse::BadRequest().json(e)
+    }
+}
+
+impl ClaimsPolicy {
+    /// Validation for the checks jsonwebtoken does itself.
+    pub fn validation(&self, alg: Algorithm) -> Validation {
+        let mut validation = Validation::new(alg);
+        validation.leeway = self.leeway;
+        validation.validate_nbf = self.validate_nbf;
+
+        let mut required = HashSet::new();
+        if self.require_exp {
+            required.insert("exp".to_string());
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required.insert("iss".to_string());
+        }
+        if self.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audience);
+            required.insert("aud".to_string());
+        }
+        validation.required_spec_claims = required;
+
+        validation
+    }
+
+    /// Checks jsonwebtoken doesn't cover: `iat` and the required custom claims.
+    pub fn check(&self, claims: &serde_json::Value) -> Result<(), ClaimsError> {
+        match self
+            .report(claims)
+            .into_iter()
+            .find(|c| !c.passed && (c.check == "iat" || self.required.contains(&c.check)))
+        {
+            Some(failed) => Err(ClaimsError::new(&failed.check, failed.message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs every check on the claims, failed checks don't stop the others.
+    pub fn report(&self, claims: &serde_json::Value) -> Vec<Check> {
+        let now = now();
+        let timestamp = |claim: &str| claims.get(claim).map(serde_json::Value::as_u64);
+        let mut checks = Vec::new();
+
+        checks.push(match timestamp("exp") {
+            None if self.require_exp => Check::failed("exp", "Missing required claim: exp"),
+            None => Check::passed("exp", "Token doesn't expire"),
+            Some(None) => Check::failed("exp", "exp is not a timestamp"),
+            Some(Some(exp)) if exp + self.leeway < now => Check::failed("exp", "Token has expired"),
+            Some(Some(_)) => Check::passed("exp", "Token hasn't expired"),
+        });
+
+        checks.push(match timestamp("nbf") {
+            None => Check::passed("nbf", "Token has no nbf claim"),
+            Some(None) => Check::failed("nbf", "nbf is not a timestamp"),
+            Some(Some(nbf)) if self.validate_nbf && nbf > now + self.leeway => {
+                Check::failed("nbf", "Token isn't valid yet")
+            }
+            Some(Some(_)) => Check::passed("nbf", "Token is valid already"),
+        });
+
+        checks.push(match timestamp("iat") {
+            None if self.require_iat => Check::failed("iat", "Missing required claim: iat"),
+            None => Check::passed("iat", "Token has no iat claim"),
+            Some(None) => Check::failed("iat", "iat is not a timestamp"),
+            Some(Some(iat)) if iat > now + self.leeway => {
+                Check::failed("iat", "Token was issued in the future")
+            }
+            Some(Some(iat))
+                if self
+                    .max_age
+                    .is_some_and(|max_age| now > iat + max_age + self.leeway) =>
+            {
+                Check::failed("iat", "Token is too old")
+            }
+            Some(Some(_)) => Check::passed("iat", "Token was issued in the past"),
+        });
+
+        if let Some(issuer) = &self.issuer {
+            checks.push(match claims.get("iss").and_then(|iss| iss.as_str()) {
+                Some(iss) if iss == issuer => Check::passed("iss", "Issuer matches"),
+                Some(iss) => Check::failed("iss", format!("Unexpected issuer {iss}")),
+                None => Check::failed("iss", "Missing required claim: iss"),
+            });
+        }
+
+        if !self.audience.is_empty() {
+            // `aud` is a single audience or a list of them
+            let audience = match claims.get("aud") {
+                Some(serde_json::Value::String(aud)) => vec![aud.as_str()],
+                Some(serde_json::Value::Array(auds)) => {
+                    auds.iter().filter_map(|aud| aud.as_str()).collect()
+                }
+                _ => Vec::new(),
+            };
+            checks.push(if audience.is_empty() {
+                Check::failed("aud", "Missing required claim: aud")
+            } else if audience
+                .iter()
+                .any(|aud| self.audience.iter().any(|a| a == aud))
+            {
+                Check::passed("aud", "Audience is accepted")
+            } else {
+                Check::failed("aud", "No accepted audience")
+            });
+        }
+
+        for claim in &self.required {
+            checks.push(match claims.get(claim) {
+                Some(_) => Check::passed(claim, "Required claim is present"),
+                None => Check::failed(claim, format!("Missing required claim: {claim}")),
+            });
+        }
+
+        checks
+    }
+}
+
+/// Outcome of one validation check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub check: String,
+    pub passed: bool,
+    pub message: String,
+}
+
+impl Check {
+    pub fn passed(check: &str, message: impl Into<String>) -> Self {
+        Check {
+            check: check.to_string(),
+            passed: true,
+            message: message.into(),
+        }
+    }
+
+    pub fn failed(check: &str, message: impl Into<String>) -> Self {
+        Check {
+            check: check.to_string(),
+            passed: false,
+            message: message.into(),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::models::keyring::Keys;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{crypto, Algorithm};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Name of the cursor secret in `Secrets.toml`, every instance needs the same one.
+pub const CURSOR_SECRET: &str = "QUOTES_CURSOR_SECRET";
+
+/// Signs pagination state into opaque cursors so the server doesn't have to keep it.
+pub struct Cursors {
+    keys: Keys,
+}
+
+impl Cursors {
+    pub fn new(secret: &str) -> Self {
+        Cursors {
+            keys: Keys::new(secret.as_bytes()),
+        }
+    }
+
+    pub fn sign<T: Serialize>(&self, cursor: &T) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap());
+        let signature = crypto::sign(payload.as_bytes(), &self.keys.encoding, Algorithm::HS256)
+            .expect("HMAC signing doesn't fail");
+
+        format!("{payload}.{signature}")
+    }
+
+    /// The cursor's state, `None` when it was tampered with or holds something else.
+    pub fn verify<T: DeserializeOwned>(&self, cursor: &str) -> Option<T> {
+        let (payload, signature) = cursor.split_once('.')?;
+        let valid = crypto::verify(
+            signature,
+            payload.as_bytes(),
+            &self.keys.decoding,
+            Algorithm::HS256,
+        )
+        .ok()?;
+        if !valid {
+            return None;
+        }
+
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+}
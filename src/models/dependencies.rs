@@ -0,0 +1,199 @@
+use cargo_manifest::{Dependency, DepsSet, Manifest};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencySource {
+    Registry,
+    Git,
+    Path,
+    // `workspace = true` without a `[workspace.dependencies]` entry to resolve it
+    Workspace,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionIssue {
+    Wildcard,
+    Unbounded,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyInfo {
+    pub name: String,
+    // crate name when the dependency is renamed with `package = "..."`
+    pub package: String,
+    pub kind: DependencyKind,
+    pub target: Option<String>,
+    pub source: DependencySource,
+    pub version: Option<String>,
+    pub version_issue: Option<VersionIssue>,
+    pub features: Vec<String>,
+    pub default_features: bool,
+    pub optional: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyReport {
+    pub dependencies: Vec<DependencyInfo>,
+    // crates declared more than once, with the tables they appear in
+    pub duplicates: BTreeMap<String, Vec<String>>,
+    pub version_issues: Vec<String>,
+}
+
+// Flags `*` requirements and requirements without an upper bound, e.g. `>= 1.0`
+fn version_issue(req: &str) -> Option<VersionIssue> {
+    let comparators = req.split(',').map(str::trim).collect::<Vec<&str>>();
+
+    if comparators.iter().any(|c| c.is_empty() || c.contains('*')) {
+        return Some(VersionIssue::Wildcard);
+    }
+
+    if comparators.iter().all(|c| c.starts_with('>')) {
+        return Some(VersionIssue::Unbounded);
+    }
+
+    None
+}
+
+fn dependency_info(
+    name: &str,
+    dependency: &Dependency,
+    kind: DependencyKind,
+    target: Option<&str>,
+    workspace: Option<&DepsSet>,
+) -> DependencyInfo {
+    let mut info = DependencyInfo {
+        name: name.to_string(),
+        package: name.to_string(),
+        kind,
+        target: target.map(str::to_string),
+        source: DependencySource::Registry,
+        version: None,
+        version_issue: None,
+        features: Vec::new(),
+        default_features: true,
+        optional: false,
+    };
+
+    match dependency {
+        Dependency::Simple(version) => {
+            info.version = Some(version.clone());
+        }
+        Dependency::Detailed(detail) => {
+            info.source = if detail.git.is_some() {
+                DependencySource::Git
+            } else if detail.path.is_some() {
+                DependencySource::Path
+            } else {
+                DependencySource::Registry
+            };
+            info.version = detail.version.clone();
+            info.package = detail.package.clone().unwrap_or(info.package);
+            info.features = detail.features.clone().unwrap_or_default();
+            info.default_features = detail.default_features.unwrap_or(true);
+            info.optional = detail.optional.unwrap_or_default();
+        }
+        Dependency::Inherited(inherited) => {
+            // Take the source and version from the workspace, features are additive
+            match workspace.and_then(|deps| deps.get(name)) {
+                Some(dependency) => {
+                    info = dependency_info(name, dependency, kind, target, None);
+                }
+                None => info.source = DependencySource::Workspace,
+            }
+            info.features
+                .extend(inherited.features.clone().unwrap_or_default());
+            info.optional = inherited.optional.unwrap_or_default();
+        }
+    }
+
+    // A registry dependency without a version accepts anything
+    info.version_issue = match (&info.version, info.source) {
+        (Some(version), _) => version_issue(version),
+        (None, DependencySource::Registry) => Some(VersionIssue::Wildcard),
+        (None, _) => None,
+    };
+
+    info
+}
+
+fn table_name(kind: DependencyKind, target: Option<&str>) -> String {
+    let table = match kind {
+        DependencyKind::Normal => "dependencies",
+        DependencyKind::Dev => "dev-dependencies",
+        DependencyKind::Build => "build-dependencies",
+    };
+
+    match target {
+        Some(target) => format!("target.{target}.{table}"),
+        None => table.to_string(),
+    }
+}
+
+/// Walks every dependency table of the manifest, including target-specific ones.
+pub fn analyze<P, W>(manifest: &Manifest<P, W>) -> DependencyReport {
+    let workspace = manifest
+        .workspace
+        .as_ref()
+        .and_then(|w| w.dependencies.as_ref());
+
+    // Collect every table along with its kind and target
+    let mut tables: Vec<(DependencyKind, Option<&str>, &DepsSet)> = Vec::new();
+    let top_level = [
+        (DependencyKind::Normal, &manifest.dependencies),
+        (DependencyKind::Dev, &manifest.dev_dependencies),
+        (DependencyKind::Build, &manifest.build_dependencies),
+    ];
+    for (kind, deps) in top_level {
+        if let Some(deps) = deps {
+            tables.push((kind, None, deps));
+        }
+    }
+    for (name, target) in manifest.target.iter().flatten() {
+        tables.push((DependencyKind::Normal, Some(name), &target.dependencies));
+        tables.push((DependencyKind::Dev, Some(name), &target.dev_dependencies));
+        tables.push((
+            DependencyKind::Build,
+            Some(name),
+            &target.build_dependencies,
+        ));
+    }
+
+    let mut dependencies = Vec::new();
+    let mut seen: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (kind, target, deps) in tables {
+        for (name, dependency) in deps {
+            let info = dependency_info(name, dependency, kind, target, workspace);
+            seen.entry(info.package.clone())
+                .or_default()
+                .push(table_name(kind, target));
+            dependencies.push(info);
+        }
+    }
+
+    let duplicates = seen
+        .into_iter()
+        .filter(|(_, tables)| tables.len() > 1)
+        .collect();
+    let version_issues = dependencies
+        .iter()
+        .filter(|d| d.version_issue.is_some())
+        .map(|d| d.name.clone())
+        .collect();
+
+    DependencyReport {
+        dependencies,
+        duplicates,
+        version_issues,
+    }
+}
@@ -0,0 +1,113 @@
+use crate::config;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Deserialize;
+
+// Protected header of every token, the content key is used directly
+const HEADER: &str = r#"{"alg":"dir","enc":"A256GCM","cty":"JWT"}"#;
+const TAG_SIZE: usize = 16;
+
+/// Encryption of gift cookies with a hex encoded 32 byte key.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    // encrypt gift cookies unless a wrap request says otherwise
+    pub enabled: bool,
+    pub key: Option<String>,
+    pub key_env: Option<String>,
+}
+
+impl EncryptionConfig {
+    fn key(&self) -> Option<Vec<u8>> {
+        let key = config::secret(self.key.as_deref(), self.key_env.as_deref())?;
+        let key = hex::decode(key).expect("Encryption key must be hex encoded");
+        assert_eq!(key.len(), 32, "Encryption key must be 32 bytes");
+
+        Some(key)
+    }
+}
+
+/// Seals tokens as compact JWE with `dir` and `A256GCM`.
+pub struct Encrypter {
+    cipher: Aes256Gcm,
+}
+
+impl Encrypter {
+    /// Without a configured key, cookies sealed before a restart can't be opened.
+    pub fn new(config: &EncryptionConfig) -> Self {
+        let cipher = match config.key() {
+            Some(key) => Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            None => Aes256Gcm::new(&Aes256Gcm::generate_key(OsRng)),
+        };
+
+        Encrypter { cipher }
+    }
+
+    pub fn seal(&self, token: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(HEADER);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        // the tag is appended to the ciphertext
+        let mut ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: token.as_bytes(),
+                    aad: header.as_bytes(),
+                },
+            )
+            .expect("token fits in a single message");
+        let tag = ciphertext.split_off(ciphertext.len() - TAG_SIZE);
+
+        // there is no encrypted key with `dir`
+        format!(
+            "{header}..{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(nonce),
+            URL_SAFE_NO_PAD.encode(ciphertext),
+            URL_SAFE_NO_PAD.encode(tag)
+        )
+    }
+
+    pub fn open(&self, jwe: &str) -> Result<String, String> {
+        let parts = jwe.split('.').collect::<Vec<&str>>();
+        let [header, key, nonce, ciphertext, tag] = parts[..] else {
+            return Err("JWE must have five parts".to_string());
+        };
+
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|e| e.to_string());
+        let protected = serde_json::from_slice::<serde_json::Value>(&decode(header)?)
+            .map_err(|e| e.to_string())?;
+        if protected["alg"] != "dir" || protected["enc"] != "A256GCM" || !key.is_empty() {
+            return Err("Unsupported JWE algorithm".to_string());
+        }
+
+        let nonce = decode(nonce)?;
+        if nonce.len() != 12 {
+            return Err("Invalid JWE nonce".to_string());
+        }
+        let mut msg = decode(ciphertext)?;
+        msg.extend(decode(tag)?);
+
+        let token = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &msg,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| "JWE could not be decrypted".to_string())?;
+
+        String::from_utf8(token).map_err(|e| e.to_string())
+    }
+}
+
+// A JWS has three parts, a compact JWE five
+pub fn is_jwe(token: &str) -> bool {
+    token.split('.').count() == 5
+}
@@ -0,0 +1,155 @@
+use crate::models::keyring::now;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+
+// Minimum time between two fetches triggered by an unknown kid or a failed fetch, in seconds
+const MIN_REFETCH: u64 = 10;
+
+/// Where the JSON Web Key Set comes from, a local file or a URL.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct JwksConfig {
+    pub path: Option<PathBuf>,
+    pub url: Option<String>,
+    // seconds before a fetched set is refreshed
+    pub refresh: u64,
+    // seconds a fetch from the URL may take
+    pub timeout: u64,
+}
+
+impl Default for JwksConfig {
+    fn default() -> Self {
+        JwksConfig {
+            path: None,
+            url: None,
+            refresh: 300,
+            timeout: 5,
+        }
+    }
+}
+
+struct CachedSet {
+    set: JwkSet,
+    fetched_at: u64,
+}
+
+struct FailedFetch {
+    at: u64,
+    error: String,
+}
+
+/// Caches the key set and refreshes it once it's stale or a token names an unknown kid.
+/// A failed refresh keeps serving the previous set.
+pub struct JwksCache {
+    config: &'static JwksConfig,
+    client: reqwest::Client,
+    cached: RwLock<Option<CachedSet>>,
+    // held while fetching so only one request fetches at a time
+    fetching: Mutex<Option<FailedFetch>>,
+}
+
+impl JwksCache {
+    pub fn new(config: &'static JwksConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("JWKS client can be built");
+
+        JwksCache {
+            config,
+            client,
+            cached: RwLock::new(None),
+            fetching: Mutex::new(None),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.config.path.is_some() || self.config.url.is_some()
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, String> {
+        if let Some(path) = &self.config.path {
+            let contents = tokio::fs::read_to_string(path)
+                .await
+                .map_err(|e| e.to_string())?;
+            return serde_json::from_str(&contents).map_err(|e| e.to_string());
+        }
+
+        let Some(url) = &self.config.url else {
+            return Err("No JWKS source configured".to_string());
+        };
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json::<JwkSet>()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    // The cached answer, `None` when the set has to be fetched first
+    async fn cached_key(&self, kid: &str) -> Option<Option<Jwk>> {
+        let cached = self.cached.read().await;
+        let cached = cached.as_ref()?;
+        let age = now().saturating_sub(cached.fetched_at);
+        let key = cached.set.find(kid);
+
+        // an unknown kid only triggers a refetch every few seconds
+        let fresh = age < self.config.refresh && (key.is_some() || age < MIN_REFETCH);
+        fresh.then(|| key.cloned())
+    }
+
+    /// Key with the given kid, `None` when no set is configured or the kid is unknown.
+    /// Fails when the set couldn't be fetched and the previous one doesn't have the kid.
+    pub async fn key(&self, kid: &str) -> Result<Option<Jwk>, String> {
+        if !self.is_configured() {
+            return Ok(None);
+        }
+
+        if let Some(key) = self.cached_key(kid).await {
+            return Ok(key);
+        }
+
+        let mut failed = self.fetching.lock().await;
+        // another request may have refreshed the set while this one waited
+        if let Some(key) = self.cached_key(kid).await {
+            return Ok(key);
+        }
+
+        // after a failed fetch the error is reused for a few seconds
+        let fetched = match failed.as_ref().filter(|f| now() < f.at + MIN_REFETCH) {
+            Some(f) => Err(f.error.clone()),
+            None => self.fetch().await.inspect_err(|error| {
+                *failed = Some(FailedFetch {
+                    at: now(),
+                    error: error.clone(),
+                })
+            }),
+        };
+
+        match fetched {
+            Ok(set) => {
+                *failed = None;
+                let key = set.find(kid).cloned();
+                *self.cached.write().await = Some(CachedSet {
+                    set,
+                    fetched_at: now(),
+                });
+
+                Ok(key)
+            }
+            Err(error) => {
+                let cached = self.cached.read().await;
+                match cached.as_ref().and_then(|c| c.set.find(kid)) {
+                    Some(key) => Ok(Some(key.clone())),
+                    None => Err(format!("JWKS refresh failed: {error}")),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::config;
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// An HMAC key from `[[keys]]`, with either `secret` or `secret_env` set.
+#[derive(Deserialize)]
+pub struct KeyConfig {
+    pub kid: String,
+    pub secret: Option<String>,
+    pub secret_env: Option<String>,
+}
+
+impl KeyConfig {
+    fn secret(&self) -> String {
+        config::secret(self.secret.as_deref(), self.secret_env.as_deref())
+            .unwrap_or_else(|| panic!("Key \"{}\" has neither `secret` nor `secret_env`", self.kid))
+    }
+}
+
+fn random(len: usize) -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), len)
+}
+
+pub fn random_kid() -> String {
+    format!("key-{}", random(12))
+}
+
+pub fn random_secret() -> String {
+    random(60)
+}
+
+pub struct Keys {
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+}
+
+impl Keys {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+}
+
+pub struct SigningKey {
+    pub kid: String,
+    pub keys: Keys,
+    // a retired key only verifies tokens until this timestamp
+    pub expires_at: Option<u64>,
+}
+
+/// A key rotated to with `/16/keys/rotate`, stored so every instance verifies with it.
+pub struct StoredKey {
+    pub kid: String,
+    pub secret: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct KeyStatus {
+    pub kid: String,
+    pub current: bool,
+    pub expires_at: Option<u64>,
+}
+
+/// The keys gift tokens are signed with.
+/// The newest key signs, every key that hasn't expired still verifies.
+pub struct KeyRing {
+    configured: Vec<SigningKey>,
+    rotated: Vec<SigningKey>,
+    // when the rotated keys were last loaded
+    synced_at: Option<u64>,
+}
+
+impl KeyRing {
+    /// Builds the ring from `[[keys]]`, falling back to a key for this process only.
+    pub fn new(configured: &[KeyConfig]) -> Self {
+        let mut keys = configured
+            .iter()
+            .map(|key| SigningKey {
+                kid: key.kid.clone(),
+                keys: Keys::new(key.secret().as_bytes()),
+                expires_at: None,
+            })
+            .collect::<Vec<SigningKey>>();
+
+        if keys.is_empty() {
+            keys.push(Self::random_key());
+        }
+
+        KeyRing {
+            configured: keys,
+            rotated: Vec::new(),
+            synced_at: None,
+        }
+    }
+
+    // Only valid for this process, other instances can't verify its tokens
+    fn random_key() -> SigningKey {
+        SigningKey {
+            kid: random_kid(),
+            keys: Keys::new(random_secret().as_bytes()),
+            expires_at: None,
+        }
+    }
+
+    /// Replaces the rotated keys, the configured keys retire once the first rotation's
+    /// tokens could have replaced all of theirs.
+    pub fn load(&mut self, stored: Vec<StoredKey>, ttl: u64) {
+        let retire_at = stored.first().map(|key| key.created_at + ttl);
+        for key in &mut self.configured {
+            key.expires_at = retire_at;
+        }
+
+        self.rotated = stored
+            .into_iter()
+            .map(|key| SigningKey {
+                kid: key.kid,
+                keys: Keys::new(key.secret.as_bytes()),
+                expires_at: key.expires_at,
+            })
+            .collect();
+        self.synced_at = Some(now());
+    }
+
+    /// Whether the rotated keys were loaded less than `max_age` seconds ago.
+    pub fn synced_within(&self, max_age: u64) -> bool {
+        self.synced_at
+            .is_some_and(|synced_at| now().saturating_sub(synced_at) < max_age)
+    }
+
+    /// Whether the kid belongs to a key from `[[keys]]`.
+    pub fn is_configured(&self, kid: &str) -> bool {
+        self.configured.iter().any(|key| key.kid == kid)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &SigningKey> {
+        self.configured.iter().chain(&self.rotated)
+    }
+
+    pub fn current(&self) -> &SigningKey {
+        self.rotated
+            .last()
+            .or(self.configured.last())
+            .expect("key ring is never empty")
+    }
+
+    /// Key for the `kid` of a token, the current key for tokens without one.
+    pub fn verifying(&self, kid: Option<&str>) -> Option<&SigningKey> {
+        let Some(kid) = kid else {
+            return Some(self.current());
+        };
+
+        self.active().find(|key| key.kid == kid)
+    }
+
+    /// Every key that still verifies tokens.
+    pub fn active(&self) -> impl Iterator<Item = &SigningKey> {
+        self.keys()
+            .filter(|key| key.expires_at.is_none_or(|exp| exp > now()))
+    }
+
+    pub fn status(&self) -> Vec<KeyStatus> {
+        let current = self.current().kid.clone();
+
+        self.active()
+            .map(|key| KeyStatus {
+                kid: key.kid.clone(),
+                current: key.kid == current,
+                expires_at: key.expires_at,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Package {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+impl Package {
+    // Identifier used for the nodes of the dependency graph
+    pub fn id(&self) -> String {
+        format!("{} {}", self.name, self.version)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockFile {
+    // Lockfile format version, absent in version 1 lockfiles
+    pub version: Option<u32>,
+    pub package: Vec<Package>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    // dependency entries that don't match any package in the lockfile
+    pub unresolved: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockFileReport {
+    pub version: u32,
+    pub graph: Graph,
+    pub duplicates: BTreeMap<String, Vec<String>>,
+    pub missing_checksums: Vec<String>,
+    pub git_sources: Vec<String>,
+    // workspace members and path dependencies have no source at all
+    pub path_sources: Vec<String>,
+}
+
+impl LockFile {
+    // Finds the package a `dependencies` entry refers to.
+    // Entries are `name`, `name version` or `name version (source)`.
+    fn resolve(&self, entry: &str) -> Option<&Package> {
+        let mut parts = entry.splitn(3, ' ');
+        let name = parts.next()?;
+        let version = parts.next();
+        let source = parts
+            .next()
+            .map(|s| s.trim_start_matches('(').trim_end_matches(')'));
+
+        let mut candidates = self.package.iter().filter(|p| {
+            p.name == name
+                && version.is_none_or(|v| p.version == v)
+                && source.is_none_or(|s| p.source.as_deref() == Some(s))
+        });
+
+        let package = candidates.next()?;
+        // an ambiguous entry can't be resolved
+        if candidates.next().is_some() {
+            return None;
+        }
+
+        Some(package)
+    }
+
+    pub fn graph(&self) -> Graph {
+        let mut graph = Graph::default();
+
+        for package in &self.package {
+            graph.nodes.push(Node {
+                id: package.id(),
+                name: package.name.clone(),
+                version: package.version.clone(),
+                source: package.source.clone(),
+            });
+
+            for entry in &package.dependencies {
+                match self.resolve(entry) {
+                    Some(dependency) => graph.edges.push(Edge {
+                        from: package.id(),
+                        to: dependency.id(),
+                    }),
+                    None => graph.unresolved.push(entry.clone()),
+                }
+            }
+        }
+
+        graph
+    }
+
+    pub fn analyze(&self) -> LockFileReport {
+        let mut versions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for package in &self.package {
+            versions
+                .entry(package.name.clone())
+                .or_default()
+                .push(package.version.clone());
+        }
+
+        let duplicates = versions
+            .into_iter()
+            .filter(|(_, versions)| versions.len() > 1)
+            .collect();
+
+        let missing_checksums = self
+            .package
+            .iter()
+            .filter(|p| p.checksum.is_none())
+            .map(Package::id)
+            .collect();
+
+        let git_sources = self
+            .package
+            .iter()
+            .filter(|p| p.source.as_ref().is_some_and(|s| s.starts_with("git+")))
+            .map(Package::id)
+            .collect();
+
+        let path_sources = self
+            .package
+            .iter()
+            .filter(|p| p.source.is_none())
+            .map(Package::id)
+            .collect();
+
+        LockFileReport {
+            version: self.version.unwrap_or(1),
+            graph: self.graph(),
+            duplicates,
+            missing_checksums,
+            git_sources,
+            path_sources,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionChange {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChecksumChange {
+    pub name: String,
+    pub version: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LockFileDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub upgraded: Vec<VersionChange>,
+    pub downgraded: Vec<VersionChange>,
+    pub checksum_changed: Vec<ChecksumChange>,
+}
+
+impl LockFileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.upgraded.is_empty()
+            && self.downgraded.is_empty()
+            && self.checksum_changed.is_empty()
+    }
+}
+
+// Orders versions by semver, falling back to plain string order for invalid versions
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    match (semver::Version::parse(a), semver::Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+impl LockFile {
+    fn versions(&self) -> BTreeMap<&str, BTreeMap<&str, Option<&str>>> {
+        let mut versions: BTreeMap<&str, BTreeMap<&str, Option<&str>>> = BTreeMap::new();
+        for package in &self.package {
+            versions
+                .entry(&package.name)
+                .or_default()
+                .insert(&package.version, package.checksum.as_deref());
+        }
+
+        versions
+    }
+
+    /// Compares two lockfiles package by package.
+    /// A crate that only changed version is reported as an upgrade or downgrade,
+    /// the versions of a crate with several versions are paired up in order.
+    pub fn diff(&self, new: &LockFile) -> LockFileDiff {
+        let mut diff = LockFileDiff::default();
+        let old_versions = self.versions();
+        let new_versions = new.versions();
+
+        let names = old_versions
+            .keys()
+            .chain(new_versions.keys())
+            .collect::<BTreeSet<_>>();
+
+        for name in names {
+            let empty = BTreeMap::new();
+            let old = old_versions.get(name).unwrap_or(&empty);
+            let new = new_versions.get(name).unwrap_or(&empty);
+
+            // Same version on both sides, only the checksum can change
+            for (version, checksum) in old {
+                if let Some(new_checksum) = new.get(version) {
+                    if checksum != new_checksum {
+                        diff.checksum_changed.push(ChecksumChange {
+                            name: name.to_string(),
+                            version: version.to_string(),
+                            from: checksum.map(str::to_string),
+                            to: new_checksum.map(str::to_string),
+                        });
+                    }
+                }
+            }
+
+            let mut removed = old
+                .keys()
+                .filter(|v| !new.contains_key(*v))
+                .collect::<Vec<_>>();
+            let mut added = new
+                .keys()
+                .filter(|v| !old.contains_key(*v))
+                .collect::<Vec<_>>();
+            removed.sort_by(|a, b| compare_versions(a, b));
+            added.sort_by(|a, b| compare_versions(a, b));
+
+            let paired = removed.len().min(added.len());
+            for (from, to) in removed.iter().zip(added.iter()) {
+                let change = VersionChange {
+                    name: name.to_string(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                };
+
+                if compare_versions(from, to).is_lt() {
+                    diff.upgraded.push(change);
+                } else {
+                    diff.downgraded.push(change);
+                }
+            }
+
+            for version in &removed[paired..] {
+                diff.removed.push(format!("{name} {version}"));
+            }
+            for version in &added[paired..] {
+                diff.added.push(format!("{name} {version}"));
+            }
+        }
+
+        diff
+    }
+}
@@ -0,0 +1,340 @@
+use cargo_manifest::{Manifest, MaybeInherited, Package, WorkspacePackage};
+use serde::Deserialize;
+use std::fmt;
+
+// Manifest with order metadata on both the package and the workspace
+pub type OrderManifest = Manifest<Metadata, Metadata>;
+
+#[derive(Default, Deserialize)]
+pub struct Metadata {
+    #[serde(default)]
+    pub orders: Vec<Order>,
+    // `workspace = true` pulls the orders in `[workspace.metadata]` into the package
+    #[serde(default)]
+    pub workspace: bool,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Deserialize)]
+pub struct Order {
+    pub item: String,
+    #[serde_as(deserialize_as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub quantity: Option<usize>,
+}
+
+// Package fields that a policy can require to be present
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageField {
+    Authors,
+    Description,
+    Documentation,
+    Homepage,
+    License,
+    LicenseFile,
+    Readme,
+    Repository,
+    Version,
+}
+
+impl PackageField {
+    fn is_present(&self, package: &Package<Metadata>) -> bool {
+        match self {
+            PackageField::Authors => package.authors.is_some(),
+            PackageField::Description => package.description.is_some(),
+            PackageField::Documentation => package.documentation.is_some(),
+            PackageField::Homepage => package.homepage.is_some(),
+            PackageField::License => package.license.is_some(),
+            PackageField::LicenseFile => package.license_file.is_some(),
+            PackageField::Readme => package.readme.is_some(),
+            PackageField::Repository => package.repository.is_some(),
+            PackageField::Version => package.version.is_some(),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            PackageField::Authors => "authors",
+            PackageField::Description => "description",
+            PackageField::Documentation => "documentation",
+            PackageField::Homepage => "homepage",
+            PackageField::License => "license",
+            PackageField::LicenseFile => "license-file",
+            PackageField::Readme => "readme",
+            PackageField::Repository => "repository",
+            PackageField::Version => "version",
+        }
+    }
+}
+
+/// Rules a manifest must satisfy before its orders are processed, loaded from `config/manifest_policy.toml`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ManifestPolicy {
+    pub required_keywords: Vec<String>,
+    pub forbidden_keywords: Vec<String>,
+    pub min_quantity: Option<usize>,
+    pub max_quantity: Option<usize>,
+    pub allowed_items: Option<Vec<String>>,
+    pub required_fields: Vec<PackageField>,
+    // body of a missing keyword violation, `{keyword}` is replaced by the keyword
+    pub missing_keyword_message: String,
+    // size in bytes of all the manifests in one multipart upload
+    pub max_upload_size: usize,
+}
+
+impl Default for ManifestPolicy {
+    fn default() -> Self {
+        ManifestPolicy {
+            required_keywords: vec!["Christmas 2024".to_string()],
+            forbidden_keywords: Vec::new(),
+            min_quantity: None,
+            max_quantity: None,
+            allowed_items: None,
+            required_fields: Vec::new(),
+            missing_keyword_message: "Magic keyword not provided".to_string(),
+            max_upload_size: 1048576,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Violation {
+    MissingKeyword { keyword: String, message: String },
+    ForbiddenKeyword(String),
+    BelowMinQuantity { item: String, quantity: usize },
+    AboveMaxQuantity { item: String, quantity: usize },
+    ItemNotAllowed(String),
+    MissingField(PackageField),
+}
+
+impl Violation {
+    // Name of the rule that was broken
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Violation::MissingKeyword { .. } => "required_keywords",
+            Violation::ForbiddenKeyword(_) => "forbidden_keywords",
+            Violation::BelowMinQuantity { .. } => "min_quantity",
+            Violation::AboveMaxQuantity { .. } => "max_quantity",
+            Violation::ItemNotAllowed(_) => "allowed_items",
+            Violation::MissingField(_) => "required_fields",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::MissingKeyword { message, .. } => write!(f, "{message}"),
+            Violation::ForbiddenKeyword(keyword) => {
+                write!(f, "keyword \"{keyword}\" is not allowed")
+            }
+            Violation::BelowMinQuantity { item, quantity } => {
+                write!(f, "{item} quantity {quantity} is below the minimum")
+            }
+            Violation::AboveMaxQuantity { item, quantity } => {
+                write!(f, "{item} quantity {quantity} is above the maximum")
+            }
+            Violation::ItemNotAllowed(item) => write!(f, "{item} is not an allowed item"),
+            Violation::MissingField(field) => {
+                write!(f, "package field {} is missing", field.as_str())
+            }
+        }
+    }
+}
+
+impl ManifestPolicy {
+    pub fn check(&self, package: &Package<Metadata>) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        // Check keywords
+        let keywords = package
+            .keywords
+            .clone()
+            .and_then(|k| k.as_local())
+            .unwrap_or_default();
+
+        for keyword in &self.required_keywords {
+            if !keywords.contains(keyword) {
+                violations.push(Violation::MissingKeyword {
+                    keyword: keyword.clone(),
+                    message: self.missing_keyword_message.replace("{keyword}", keyword),
+                });
+            }
+        }
+
+        for keyword in &self.forbidden_keywords {
+            if keywords.contains(keyword) {
+                violations.push(Violation::ForbiddenKeyword(keyword.clone()));
+            }
+        }
+
+        // Check package fields
+        for field in &self.required_fields {
+            if !field.is_present(package) {
+                violations.push(Violation::MissingField(*field));
+            }
+        }
+
+        // Check orders, only the ones with a valid quantity are considered
+        let orders = package.metadata.iter().flat_map(|m| m.orders.iter());
+        for order in orders {
+            let Some(quantity) = order.quantity else {
+                continue;
+            };
+
+            if let Some(allowed) = &self.allowed_items {
+                if !allowed.contains(&order.item) {
+                    violations.push(Violation::ItemNotAllowed(order.item.clone()));
+                }
+            }
+
+            if self.min_quantity.is_some_and(|min| quantity < min) {
+                violations.push(Violation::BelowMinQuantity {
+                    item: order.item.clone(),
+                    quantity,
+                });
+            }
+
+            if self.max_quantity.is_some_and(|max| quantity > max) {
+                violations.push(Violation::AboveMaxQuantity {
+                    item: order.item.clone(),
+                    quantity,
+                });
+            }
+        }
+
+        violations
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WorkspaceError {
+    NoPackage,
+    MultipleRoots,
+    NotInherited(&'static str),
+}
+
+impl fmt::Display for WorkspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkspaceError::NoPackage => write!(f, "No package in manifest"),
+            WorkspaceError::MultipleRoots => write!(f, "More than one workspace root provided"),
+            WorkspaceError::NotInherited(field) => {
+                write!(f, "{field} is inherited but not set in the workspace")
+            }
+        }
+    }
+}
+
+// Replace a `field.workspace = true` entry with the value from `[workspace.package]`
+fn inherit<T: Clone>(
+    field: &mut Option<MaybeInherited<T>>,
+    value: Option<&T>,
+    name: &'static str,
+) -> Result<(), WorkspaceError> {
+    if let Some(MaybeInherited::Inherited { .. }) = field {
+        let value = value.ok_or(WorkspaceError::NotInherited(name))?;
+        *field = Some(MaybeInherited::Local(value.clone()));
+    }
+
+    Ok(())
+}
+
+fn resolve_package(
+    package: &mut Package<Metadata>,
+    workspace: Option<&WorkspacePackage>,
+    workspace_orders: &[Order],
+) -> Result<(), WorkspaceError> {
+    let ws = workspace;
+    inherit(
+        &mut package.version,
+        ws.and_then(|w| w.version.as_ref()),
+        "version",
+    )?;
+    inherit(
+        &mut package.authors,
+        ws.and_then(|w| w.authors.as_ref()),
+        "authors",
+    )?;
+    inherit(
+        &mut package.description,
+        ws.and_then(|w| w.description.as_ref()),
+        "description",
+    )?;
+    inherit(
+        &mut package.documentation,
+        ws.and_then(|w| w.documentation.as_ref()),
+        "documentation",
+    )?;
+    inherit(
+        &mut package.homepage,
+        ws.and_then(|w| w.homepage.as_ref()),
+        "homepage",
+    )?;
+    inherit(
+        &mut package.readme,
+        ws.and_then(|w| w.readme.as_ref()),
+        "readme",
+    )?;
+    inherit(
+        &mut package.keywords,
+        ws.and_then(|w| w.keywords.as_ref()),
+        "keywords",
+    )?;
+    inherit(
+        &mut package.license,
+        ws.and_then(|w| w.license.as_ref()),
+        "license",
+    )?;
+    inherit(
+        &mut package.license_file,
+        ws.and_then(|w| w.license_file.as_ref()),
+        "license-file",
+    )?;
+    inherit(
+        &mut package.repository,
+        ws.and_then(|w| w.repository.as_ref()),
+        "repository",
+    )?;
+
+    // Inherited orders come before the package's own orders
+    if let Some(metadata) = package.metadata.as_mut().filter(|m| m.workspace) {
+        let mut orders = workspace_orders.to_vec();
+        orders.append(&mut metadata.orders);
+        metadata.orders = orders;
+    }
+
+    Ok(())
+}
+
+/// Resolves every package against the workspace root, if one of the manifests is a root.
+/// Packages are returned in the order the manifests were given.
+pub fn resolve_packages(
+    manifests: Vec<OrderManifest>,
+) -> Result<Vec<Package<Metadata>>, WorkspaceError> {
+    let mut roots = manifests.iter().filter_map(|m| m.workspace.as_ref());
+    let root = roots.next();
+    if roots.next().is_some() {
+        return Err(WorkspaceError::MultipleRoots);
+    }
+
+    let workspace_package = root.and_then(|w| w.package.clone());
+    let workspace_orders = root
+        .and_then(|w| w.metadata.as_ref())
+        .map(|m| m.orders.clone())
+        .unwrap_or_default();
+
+    let mut packages = Vec::new();
+    for mut package in manifests.into_iter().filter_map(|m| m.package) {
+        resolve_package(&mut package, workspace_package.as_ref(), &workspace_orders)?;
+        packages.push(package);
+    }
+
+    if packages.is_empty() {
+        return Err(WorkspaceError::NoPackage);
+    }
+
+    Ok(packages)
+}
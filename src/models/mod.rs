@@ -1 +1,12 @@
 pub mod board;
+pub mod claims;
+pub mod cursor;
+pub mod dependencies;
+pub mod jwe;
+pub mod jwks;
+pub mod keyring;
+pub mod lockfile;
+pub mod manifest;
+pub mod ornament;
+pub mod palette;
+pub mod signer;
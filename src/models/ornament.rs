@@ -0,0 +1,311 @@
+use serde::Deserialize;
+use tiny_skia::{FillRule, Paint, PathBuilder, Pixmap, Rect, Transform};
+
+// Size of the lockfile canvas and of the ornaments drawn on it, in pixels
+pub const CANVAS_SIZE: u32 = 276;
+pub const ORNAMENT_SIZE: u32 = 20;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    #[default]
+    Circle,
+    Square,
+    Diamond,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Animation {
+    #[default]
+    None,
+    Pulse,
+    Spin,
+    Twinkle,
+}
+
+impl Animation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Animation::None => "none",
+            Animation::Pulse => "pulse",
+            Animation::Spin => "spin",
+            Animation::Twinkle => "twinkle",
+        }
+    }
+}
+
+// An ornament drawn from a package checksum
+pub struct Ornament {
+    pub color: String,
+    pub top: u32,
+    pub left: u32,
+    pub size: u32,
+    pub shape: Shape,
+    // opacity in percent
+    pub opacity: u8,
+    pub animation: Animation,
+}
+
+impl Ornament {
+    // Center of the ornament on the canvas
+    pub fn cx(&self) -> u32 {
+        self.left + self.size / 2
+    }
+
+    pub fn cy(&self) -> u32 {
+        self.top + self.size / 2
+    }
+
+    pub fn radius(&self) -> u32 {
+        self.size / 2
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.opacity as f32 / 100.0
+    }
+
+    /// CSS for everything that differs from a plain ornament, empty for the classic mapping.
+    pub fn extra_style(&self) -> String {
+        let mut style = String::new();
+
+        if self.size != ORNAMENT_SIZE {
+            style.push_str(&format!("width:{0}px;height:{0}px;", self.size));
+        }
+
+        match self.shape {
+            Shape::Circle => {}
+            Shape::Square => style.push_str("border-radius:0;"),
+            Shape::Diamond => style.push_str("border-radius:0;transform:rotate(45deg);"),
+        }
+
+        if self.opacity != 100 {
+            style.push_str(&format!("opacity:{};", self.alpha()));
+        }
+
+        if self.animation != Animation::None {
+            style.push_str(&format!(
+                "animation:ornament-{} 2s infinite;",
+                self.animation.as_str()
+            ));
+        }
+
+        style
+    }
+
+    // Keep the whole ornament inside the canvas
+    fn cap(&mut self) {
+        self.size = self.size.clamp(1, CANVAS_SIZE);
+        self.top = self.top.min(CANVAS_SIZE - self.size);
+        self.left = self.left.min(CANVAS_SIZE - self.size);
+        self.opacity = self.opacity.min(100);
+    }
+
+    fn rgb(&self) -> (u8, u8, u8) {
+        let color = u32::from_str_radix(&self.color, 16).unwrap_or_default();
+
+        ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+    }
+}
+
+/// Turns the decoded bytes of a checksum into an ornament.
+pub trait OrnamentMapping {
+    // Number of checksum bytes the mapping reads
+    fn bytes_needed(&self) -> usize;
+
+    fn map(&self, bytes: &[u8]) -> Ornament;
+}
+
+// The original mapping: bytes 0..3 give the color, byte 3 the top and byte 4 the left offset
+pub struct ClassicMapping;
+
+impl OrnamentMapping for ClassicMapping {
+    fn bytes_needed(&self) -> usize {
+        5
+    }
+
+    fn map(&self, bytes: &[u8]) -> Ornament {
+        Ornament {
+            color: hex::encode(&bytes[0..3]),
+            top: bytes[3] as u32,
+            left: bytes[4] as u32,
+            size: ORNAMENT_SIZE,
+            shape: Shape::Circle,
+            opacity: 100,
+            animation: Animation::None,
+        }
+    }
+}
+
+/// Reads every property from a configurable byte of the checksum.
+/// Properties without a byte keep their classic value.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ByteMapping {
+    // first of the three color bytes
+    pub color: usize,
+    pub top: usize,
+    pub left: usize,
+    pub size: Option<usize>,
+    pub shape: Option<usize>,
+    pub opacity: Option<usize>,
+    pub animation: Option<usize>,
+    pub min_size: u32,
+    pub max_size: u32,
+    pub min_opacity: u8,
+    pub shapes: Vec<Shape>,
+    pub animations: Vec<Animation>,
+}
+
+impl Default for ByteMapping {
+    fn default() -> Self {
+        ByteMapping {
+            color: 0,
+            top: 3,
+            left: 4,
+            size: Some(5),
+            shape: Some(6),
+            opacity: Some(7),
+            animation: Some(8),
+            min_size: 10,
+            max_size: 30,
+            min_opacity: 40,
+            shapes: vec![Shape::Circle, Shape::Square, Shape::Diamond],
+            animations: vec![
+                Animation::None,
+                Animation::Pulse,
+                Animation::Spin,
+                Animation::Twinkle,
+            ],
+        }
+    }
+}
+
+impl OrnamentMapping for ByteMapping {
+    fn bytes_needed(&self) -> usize {
+        [
+            Some(self.color + 3),
+            Some(self.top + 1),
+            Some(self.left + 1),
+            self.size.map(|b| b + 1),
+            self.shape.map(|b| b + 1),
+            self.opacity.map(|b| b + 1),
+            self.animation.map(|b| b + 1),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default()
+    }
+
+    fn map(&self, bytes: &[u8]) -> Ornament {
+        // Scale a byte into the range `min..=max`
+        let scale = |byte: u8, min: u32, max: u32| {
+            min + (byte as u32 * max.saturating_sub(min)) / u8::MAX as u32
+        };
+        let pick = |byte: Option<usize>, len: usize| byte.map(|b| bytes[b] as usize % len.max(1));
+
+        Ornament {
+            color: hex::encode(&bytes[self.color..self.color + 3]),
+            top: bytes[self.top] as u32,
+            left: bytes[self.left] as u32,
+            size: self
+                .size
+                .map(|b| scale(bytes[b], self.min_size, self.max_size))
+                .unwrap_or(ORNAMENT_SIZE),
+            shape: pick(self.shape, self.shapes.len())
+                .and_then(|i| self.shapes.get(i).copied())
+                .unwrap_or_default(),
+            opacity: self
+                .opacity
+                .map(|b| scale(bytes[b], self.min_opacity as u32, 100) as u8)
+                .unwrap_or(100),
+            animation: pick(self.animation, self.animations.len())
+                .and_then(|i| self.animations.get(i).copied())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Mapping used for lockfile ornaments, selected with `mapping` in the `[ornaments]` config.
+#[derive(Default, Deserialize)]
+#[serde(tag = "mapping", rename_all = "lowercase")]
+pub enum MappingConfig {
+    #[default]
+    Classic,
+    Bytes(ByteMapping),
+}
+
+impl MappingConfig {
+    pub fn mapping(&self) -> &dyn OrnamentMapping {
+        match self {
+            MappingConfig::Classic => &ClassicMapping,
+            MappingConfig::Bytes(mapping) => mapping,
+        }
+    }
+}
+
+pub fn generate_ornament(
+    checksum: &str,
+    mapping: &dyn OrnamentMapping,
+) -> Result<Ornament, String> {
+    let bytes = hex::decode(checksum).map_err(|e| format!("Invalid Checksum: {e}"))?;
+
+    if bytes.len() < mapping.bytes_needed() {
+        return Err(format!("Invalid Checksum: {}", checksum));
+    }
+
+    let mut ornament = mapping.map(&bytes);
+    ornament.cap();
+
+    Ok(ornament)
+}
+
+/// Rasterizes the ornaments of every lockfile into a PNG, one canvas per lockfile side by side.
+pub fn render_png(lockfiles: &[Vec<Ornament>]) -> Option<Vec<u8>> {
+    let width = CANVAS_SIZE * lockfiles.len().max(1) as u32;
+    let mut pixmap = Pixmap::new(width, CANVAS_SIZE)?;
+
+    for (idx, ornaments) in lockfiles.iter().enumerate() {
+        let offset = (CANVAS_SIZE * idx as u32) as f32;
+
+        for ornament in ornaments {
+            let (cx, cy) = (offset + ornament.cx() as f32, ornament.cy() as f32);
+            let half = ornament.size as f32 / 2.0;
+
+            let path = match ornament.shape {
+                Shape::Circle => PathBuilder::from_circle(cx, cy, half)?,
+                Shape::Square => PathBuilder::from_rect(Rect::from_xywh(
+                    cx - half,
+                    cy - half,
+                    ornament.size as f32,
+                    ornament.size as f32,
+                )?),
+                Shape::Diamond => {
+                    let mut pb = PathBuilder::new();
+                    pb.move_to(cx, cy - half);
+                    pb.line_to(cx + half, cy);
+                    pb.line_to(cx, cy + half);
+                    pb.line_to(cx - half, cy);
+                    pb.close();
+                    pb.finish()?
+                }
+            };
+
+            let (r, g, b) = ornament.rgb();
+            let mut paint = Paint::default();
+            paint.set_color_rgba8(r, g, b, (ornament.alpha() * 255.0) as u8);
+            paint.anti_alias = true;
+
+            pixmap.fill_path(
+                &path,
+                &paint,
+                FillRule::Winding,
+                Transform::identity(),
+                None,
+            );
+        }
+    }
+
+    pixmap.encode_png().ok()
+}
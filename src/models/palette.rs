@@ -0,0 +1,114 @@
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PaletteColor {
+    pub name: String,
+    pub hex: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<PaletteColor>,
+}
+
+impl Palette {
+    pub fn classic() -> Self {
+        let color = |name: &str, hex: &str| PaletteColor {
+            name: name.to_string(),
+            hex: hex.to_string(),
+        };
+
+        Palette {
+            name: "classic".to_string(),
+            colors: vec![
+                color("red", "#ff0000"),
+                color("blue", "#0000ff"),
+                color("purple", "#800080"),
+            ],
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PaletteColor> {
+        self.colors.iter().find(|c| c.name == name)
+    }
+
+    // Error message for a color that isn't in the palette
+    pub fn unknown_color(&self, name: &str) -> String {
+        let valid = self
+            .colors
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        format!("Unknown color \"{name}\", valid colors are: {valid}")
+    }
+}
+
+/// How presents move from one color to the next.
+/// Every field can be overridden per tree, unset fields fall back to the configured rules.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CycleRules {
+    pub palette: String,
+    // custom order of the colors, the palette order when unset
+    pub order: Option<Vec<String>>,
+    pub random: bool,
+    // colors a present never changes to
+    pub skip: Vec<String>,
+}
+
+impl Default for CycleRules {
+    fn default() -> Self {
+        CycleRules {
+            palette: "classic".to_string(),
+            order: None,
+            random: false,
+            skip: Vec::new(),
+        }
+    }
+}
+
+impl CycleRules {
+    /// Next color after `current`, `None` if `current` isn't in the palette.
+    pub fn next<'a>(&self, palette: &'a Palette, current: &str) -> Option<&'a PaletteColor> {
+        let current = palette.get(current)?;
+
+        let cycle = match &self.order {
+            Some(order) => order.iter().filter_map(|name| palette.get(name)).collect(),
+            None => palette.colors.iter().collect::<Vec<&PaletteColor>>(),
+        };
+        let cycle = cycle
+            .into_iter()
+            .filter(|c| !self.skip.contains(&c.name))
+            .collect::<Vec<&PaletteColor>>();
+
+        // Everything is skipped, the present keeps its color
+        if cycle.is_empty() {
+            return Some(current);
+        }
+
+        if self.random {
+            let others = cycle
+                .iter()
+                .copied()
+                .filter(|c| c.name != current.name)
+                .collect::<Vec<&PaletteColor>>();
+
+            return others
+                .choose(&mut rand::thread_rng())
+                .or(cycle.first())
+                .copied();
+        }
+
+        // A color outside the cycle moves to the start of it
+        let next = match cycle.iter().position(|c| c.name == current.name) {
+            Some(idx) => cycle[(idx + 1) % cycle.len()],
+            None => cycle[0],
+        };
+
+        Some(next)
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use p256::{elliptic_curve::sec1::ToEncodedPoint, SecretKey};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    traits::PublicKeyParts,
+    RsaPrivateKey,
+};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+const RSA_ALGORITHMS: [Algorithm; 6] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+];
+const EC_ALGORITHMS: [Algorithm; 1] = [Algorithm::ES256];
+
+/// Private key for `/16/sign`, a PEM file or an environment variable holding the PEM.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SignerConfig {
+    // anyone can mint tokens /16/decode accepts, so it's only meant for local testing
+    pub enabled: bool,
+    pub kid: String,
+    pub private_key_path: Option<PathBuf>,
+    pub private_key_env: Option<String>,
+}
+
+impl Default for SignerConfig {
+    fn default() -> Self {
+        SignerConfig {
+            enabled: false,
+            kid: "santa-signer".to_string(),
+            private_key_path: None,
+            private_key_env: None,
+        }
+    }
+}
+
+enum PrivateKey {
+    Rsa(Box<RsaPrivateKey>),
+    Ec(SecretKey),
+}
+
+impl PrivateKey {
+    // PKCS#8 and PKCS#1 RSA keys, PKCS#8 and SEC1 P-256 keys
+    fn from_pem(pem: &str) -> Option<Self> {
+        RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .map(|key| PrivateKey::Rsa(Box::new(key)))
+            .ok()
+            .or_else(|| {
+                SecretKey::from_pkcs8_pem(pem)
+                    .or_else(|_| SecretKey::from_sec1_pem(pem))
+                    .map(PrivateKey::Ec)
+                    .ok()
+            })
+    }
+}
+
+/// Signs claims with the configured private key and publishes the matching public key.
+pub struct Signer {
+    kid: String,
+    key: PrivateKey,
+    encoding: EncodingKey,
+}
+
+impl Signer {
+    /// Loads the configured PEM, or generates a P-256 key for this process.
+    pub fn new(config: &SignerConfig) -> Self {
+        let pem = match &config.private_key_path {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .unwrap_or_else(|e| panic!("Can't read {}: {e}", path.display())),
+            ),
+            None => config::secret(None, config.private_key_env.as_deref()),
+        };
+
+        let key = match pem {
+            Some(pem) => {
+                PrivateKey::from_pem(&pem).expect("Signing key must be an RSA or P-256 key")
+            }
+            None => PrivateKey::Ec(SecretKey::random(&mut rand::rngs::OsRng)),
+        };
+
+        // jsonwebtoken takes RSA keys as PKCS#1 and EC keys as PKCS#8
+        let encoding = match &key {
+            PrivateKey::Rsa(key) => {
+                EncodingKey::from_rsa_der(key.to_pkcs1_der().unwrap().as_bytes())
+            }
+            PrivateKey::Ec(key) => EncodingKey::from_ec_der(key.to_pkcs8_der().unwrap().as_bytes()),
+        };
+
+        Signer {
+            kid: config.kid.clone(),
+            key,
+            encoding,
+        }
+    }
+
+    /// Algorithms the key can sign with, the first one is the default.
+    pub fn algorithms(&self) -> &'static [Algorithm] {
+        match self.key {
+            PrivateKey::Rsa(_) => &RSA_ALGORITHMS,
+            PrivateKey::Ec(_) => &EC_ALGORITHMS,
+        }
+    }
+
+    pub fn sign(&self, alg: Algorithm, claims: &serde_json::Value) -> Result<String, String> {
+        if !self.algorithms().contains(&alg) {
+            let supported = self
+                .algorithms()
+                .iter()
+                .map(|alg| format!("{alg:?}"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            return Err(format!(
+                "The key can't sign {alg:?}, supported algorithms are: {supported}"
+            ));
+        }
+
+        let mut header = Header::new(alg);
+        header.kid = Some(self.kid.clone());
+
+        jsonwebtoken::encode(&header, claims, &self.encoding).map_err(|e| e.to_string())
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn decoding_key(&self) -> DecodingKey {
+        let pem = self.public_pem();
+
+        match self.key {
+            PrivateKey::Rsa(_) => DecodingKey::from_rsa_pem(pem.as_bytes()),
+            PrivateKey::Ec(_) => DecodingKey::from_ec_pem(pem.as_bytes()),
+        }
+        .expect("public key is valid PEM")
+    }
+
+    pub fn public_pem(&self) -> String {
+        match &self.key {
+            PrivateKey::Rsa(key) => key.to_public_key().to_public_key_pem(LineEnding::LF),
+            PrivateKey::Ec(key) => key.public_key().to_public_key_pem(LineEnding::LF),
+        }
+        .expect("public key can be encoded")
+    }
+
+    pub fn jwk(&self) -> serde_json::Value {
+        let encode = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+
+        match &self.key {
+            PrivateKey::Rsa(key) => serde_json::json!({
+                "kty": "RSA",
+                "use": "sig",
+                "kid": self.kid,
+                "n": encode(&key.n().to_bytes_be()),
+                "e": encode(&key.e().to_bytes_be()),
+            }),
+            PrivateKey::Ec(key) => {
+                let point = key.public_key().to_encoded_point(false);
+                serde_json::json!({
+                    "kty": "EC",
+                    "use": "sig",
+                    "alg": "ES256",
+                    "kid": self.kid,
+                    "crv": "P-256",
+                    "x": point.x().map(|x| encode(x)),
+                    "y": point.y().map(|y| encode(y)),
+                })
+            }
+        }
+    }
+}